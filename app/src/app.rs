@@ -1,17 +1,55 @@
 use super::client::Client;
-use crate::ChannelKind;
-use crate::Guild;
-use crate::Message;
+use crate::MessageRef;
+use crate::PendingMessage;
+use crate::client::ClientState;
+use crate::client::RecvError;
 use crate::client::RecvResult;
-use crate::mock::mock_guilds;
+use crate::login::LoginScreen;
 use crate::panels::AwesomeCentralPanel;
 use crate::panels::AwesomePanelResponse;
 use crate::panels::ChannelsPanel;
+use crate::panels::ChannelsPanelResponse;
 use crate::panels::GuildsPanel;
 use crate::panels::GuildsPanelResponse;
 use crate::panels::MembersPanel;
 use crate::panels::MessageBox;
 use crate::panels::MessageBoxResponse;
+use crate::store::MESSAGE_PAGE_SIZE;
+use crate::store::Store;
+use crate::time;
+use crate::widgets::MessageResponse;
+use common::Ack;
+use common::AddReaction;
+use common::Channel;
+use common::ChannelKind;
+use common::CreateGuild;
+use common::CreateInvite;
+use common::DeleteMessage;
+use common::EditMessage;
+use common::FetchMessageRevisions;
+use common::FetchMessages;
+use common::INVITE_PATH;
+use common::Invite;
+use common::InvitePreview;
+use common::JoinGuild;
+use common::JoinGuildByCode;
+use common::MESSAGE_PATH;
+use common::MarkRead;
+use common::MessageCursor;
+use common::MessageLink;
+use common::MessageRevisions;
+use common::Nonce;
+use common::PinMessage;
+use common::PreviewInvite;
+use common::Request;
+use common::RequestId;
+use common::SendMessage;
+use common::ServerMessage;
+use common::Snowflake;
+use common::TextChannel;
+use common::User;
+use common::parse_invite;
+use common::parse_message_link;
 use eframe::CreationContext;
 use egui::CentralPanel;
 use egui::FontData;
@@ -19,100 +57,395 @@ use egui::FontDefinitions;
 use egui::Label;
 use egui::Modal;
 use egui::ModalResponse;
+use egui::ScrollArea;
+use egui::TopBottomPanel;
 use egui_extras::install_image_loaders;
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 #[derive(Clone, Copy)]
 pub enum CurrentModal {
-    CreateOrJoinModal,
-    CreateModal,
-    JoinModal,
+    CreateOrJoin,
+    Create,
+    Join,
+    Invite,
 }
 
 pub struct App {
     pub buffer: String,
     pub guild_image_buffer: String,
-    pub guilds: Vec<Guild>,
+    pub store: Store,
     pub selected_guild: Option<usize>,
-    pub client: Client,
+    pub client: Option<Client>,
     pub show_members: bool,
     pub show_current_modal: Option<CurrentModal>,
-    pub me: u32,
+    pub me: Option<User>,
+    pub login: LoginScreen,
+    pub connection_error: Option<String>,
+    pub create_guild_request: Option<RequestId>,
+    pub create_guild_error: Option<String>,
+    pub last_nonce: Nonce,
+    pub join_code_buffer: String,
+    pub join_preview: Option<InvitePreview>,
+    pub preview_request: Option<RequestId>,
+    pub join_request: Option<RequestId>,
+    pub join_error: Option<String>,
+    pub invite: Option<Invite>,
+    pub invite_request: Option<RequestId>,
+    pub invite_error: Option<String>,
+    pub editing: Option<MessageRef>,
+    pub replying: Option<MessageRef>,
+    /// Requests acting on existing messages, e.g. edits or fetching revisions, whose errors are
+    /// shown above the message box.
+    pub message_requests: HashSet<RequestId>,
+    pub message_error: Option<String>,
+    pub revisions: Option<MessageRevisions>,
 }
 
+const SERVER_URL: &str = "127.0.0.1:3000";
+const INVITE_MAX_AGE_SECS: u32 = 7 * 24 * 60 * 60;
+
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
+        if self.client.is_none() {
+            if let Some((token, user)) = self.login.show(ctx, SERVER_URL) {
+                match Client::new(format!("ws://{}/ws", SERVER_URL), &token, ctx.clone()) {
+                    Ok(client) => {
+                        self.client = Some(client);
+                        self.me = Some(user);
+                    }
+                    Err(err) => self.login.set_error(err),
+                }
+            }
+            return;
+        }
+
         self.panels(ctx);
         self.modals(ctx);
+        self.revisions_window(ctx);
         self.update_client();
     }
 }
 
 impl App {
     pub fn new(cc: &CreationContext) -> Self {
-        let client =
-            Client::new("ws://127.0.0.1:3000/ws").expect("Failed to spawn websocket thread xd");
-
         install_image_loaders(&cc.egui_ctx);
         install_fonts(cc);
 
         App {
             buffer: String::new(),
             guild_image_buffer: String::new(),
-            guilds: mock_guilds(),
+            store: Store::default(),
             selected_guild: None,
-            client,
+            client: None,
             show_members: true,
             show_current_modal: None,
-            me: 1,
+            me: None,
+            login: LoginScreen::default(),
+            connection_error: None,
+            create_guild_request: None,
+            create_guild_error: None,
+            last_nonce: 0,
+            join_code_buffer: String::new(),
+            join_preview: None,
+            preview_request: None,
+            join_request: None,
+            join_error: None,
+            invite: None,
+            invite_request: None,
+            invite_error: None,
+            editing: None,
+            replying: None,
+            message_requests: HashSet::new(),
+            message_error: None,
+            revisions: None,
         }
     }
 
     fn update_client(&mut self) {
         loop {
-            match self.client.recieve() {
+            let Some(client) = &mut self.client else {
+                return;
+            };
+            match client.recieve() {
                 RecvResult::OkNone => {
                     break;
                 }
                 RecvResult::Connecting => {
                     break;
                 }
-                RecvResult::Disconnected => {
-                    break;
-                }
-                RecvResult::Error(_) => {
+                RecvResult::Error(RecvError::WsError(_)) => {}
+                RecvResult::Error(err) => {
+                    self.connection_error = Some(err.to_string());
                     break;
                 }
                 RecvResult::Ok(server_message) => {
-                    dbg!(server_message);
+                    self.handle_server_message(server_message);
+                }
+            }
+        }
+    }
+
+    fn handle_server_message(&mut self, msg: ServerMessage) {
+        match msg {
+            ServerMessage::Welcome(_) => {
+                self.connection_error = None;
+            }
+            ServerMessage::Refused(_)
+            | ServerMessage::Resumed(_)
+            | ServerMessage::InvalidSession
+            | ServerMessage::Sequenced { .. }
+            | ServerMessage::Heartbeat
+            | ServerMessage::Pong { .. }
+            | ServerMessage::Reconnect { .. }
+            | ServerMessage::RateLimited { .. } => {}
+            ServerMessage::Ready(ready) => {
+                let selected = self
+                    .selected_guild
+                    .and_then(|i| self.store.guilds.get(i))
+                    .map(|view| view.guild.id);
+                self.me = Some(ready.user.clone());
+                self.store.load(ready);
+                self.selected_guild = selected.and_then(|id| self.store.guild_index(id));
+            }
+            ServerMessage::Ack(Ack { request_id }) => {
+                if self.create_guild_request == Some(request_id) {
+                    self.create_guild_request = None;
+                    self.show_current_modal = None;
+                    self.buffer.clear();
+                    self.guild_image_buffer.clear();
+                } else if self.preview_request == Some(request_id) {
+                    self.preview_request = None;
+                } else if self.join_request == Some(request_id) {
+                    self.join_request = None;
+                    self.show_current_modal = None;
+                    self.join_code_buffer.clear();
+                    self.join_preview = None;
+                } else if self.invite_request == Some(request_id) {
+                    self.invite_request = None;
+                } else {
+                    self.message_requests.remove(&request_id);
+                }
+            }
+            ServerMessage::Error(err) => {
+                let Some(request_id) = err.request_id else {
+                    return;
+                };
+                if self.create_guild_request == Some(request_id) {
+                    self.create_guild_request = None;
+                    self.create_guild_error = Some(err.message);
+                    return;
+                }
+                if self.preview_request == Some(request_id) || self.join_request == Some(request_id)
+                {
+                    self.preview_request = None;
+                    self.join_request = None;
+                    self.join_preview = None;
+                    self.join_error = Some(err.message);
+                    return;
+                }
+                if self.invite_request == Some(request_id) {
+                    self.invite_request = None;
+                    self.invite_error = Some(err.message);
+                    return;
+                }
+                if self.message_requests.remove(&request_id) {
+                    self.message_error = Some(err.message);
+                    return;
+                }
+                if let Some(pending) = self
+                    .store
+                    .guilds
+                    .iter_mut()
+                    .flat_map(|view| view.pending_messages.iter_mut())
+                    .find(|pending| pending.request_id == request_id)
+                {
+                    pending.failed = true;
+                }
+                // Stop paging through a channel whose history can't be fetched.
+                if let Some(history) = self
+                    .store
+                    .guilds
+                    .iter_mut()
+                    .flat_map(|view| view.history.values_mut())
+                    .find(|history| history.loading == Some(request_id))
+                {
+                    history.loading = None;
+                    history.reached_start = true;
+                }
+            }
+            ServerMessage::JoinGuild(JoinGuild { guild }) => {
+                self.selected_guild = Some(self.store.join_guild(guild));
+            }
+            ServerMessage::MemberJoin(member_join) => {
+                self.store.member_join(member_join);
+            }
+            ServerMessage::InviteCreated(invite) => {
+                self.invite = Some(invite);
+            }
+            ServerMessage::InvitePreview(preview) => {
+                self.join_preview = Some(preview);
+            }
+            ServerMessage::Messages(messages) => {
+                self.store.messages(messages);
+            }
+            ServerMessage::MessageCreate(message_create) => {
+                self.store.message_create(message_create);
+            }
+            ServerMessage::ReadState(read_state) => {
+                self.store.read_state(read_state);
+            }
+            ServerMessage::MessageUpdate(message_update) => {
+                self.store.message_update(message_update);
+            }
+            ServerMessage::MessageDelete(message_delete) => {
+                if self
+                    .editing
+                    .is_some_and(|editing| editing.message_id == message_delete.message_id)
+                {
+                    self.editing = None;
+                    self.buffer.clear();
+                }
+                if self
+                    .replying
+                    .is_some_and(|replying| replying.message_id == message_delete.message_id)
+                {
+                    self.replying = None;
                 }
+                self.store.message_delete(message_delete);
+            }
+            ServerMessage::MessageRevisions(revisions) => {
+                self.revisions = Some(revisions);
+            }
+            ServerMessage::ReactionAdd(reaction_add) => {
+                self.store.reaction_add(reaction_add);
             }
         }
     }
 
+    /// Earlier versions of a message, for moderators who clicked its "(edited)" marker.
+    fn revisions_window(&mut self, ctx: &egui::Context) {
+        let Some(revisions) = &self.revisions else {
+            return;
+        };
+        let mut open = true;
+        egui::Window::new("Edit history")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ScrollArea::vertical().show(ui, |ui| {
+                    if revisions.revisions.is_empty() {
+                        ui.weak("This message has no earlier versions.");
+                    }
+                    for revision in &revisions.revisions {
+                        ui.weak(time::message_time(revision.written_at))
+                            .on_hover_text(time::full_time(revision.written_at));
+                        ui.label(&revision.content);
+                        ui.separator();
+                    }
+                });
+            });
+        if !open {
+            self.revisions = None;
+        }
+    }
+
     fn modals(&mut self, ctx: &egui::Context) {
         if let Some(current_modal) = self.show_current_modal {
             let backdrop_response = match current_modal {
-                CurrentModal::CreateOrJoinModal => {
+                CurrentModal::CreateOrJoin => {
                     let response = create_or_join_guild_modal(ctx);
                     if let Some(res) = response.inner {
                         match res {
                             CreateOrJoin::Create => {
-                                self.show_current_modal = Some(CurrentModal::CreateModal)
+                                self.show_current_modal = Some(CurrentModal::Create)
                             }
                             CreateOrJoin::Join => {
-                                self.show_current_modal = Some(CurrentModal::JoinModal)
+                                self.show_current_modal = Some(CurrentModal::Join)
                             }
                         }
                     }
                     response.backdrop_response
                 }
-                CurrentModal::CreateModal => {
-                    create_guild_modal(ctx, &mut self.buffer, &mut self.guild_image_buffer)
-                        .backdrop_response
+                CurrentModal::Create => {
+                    let Some(client) = &mut self.client else {
+                        return;
+                    };
+                    let pending = self
+                        .create_guild_request
+                        .is_some_and(|id| client.is_pending(id));
+                    let response = create_guild_modal(
+                        ctx,
+                        &mut self.buffer,
+                        &mut self.guild_image_buffer,
+                        pending,
+                        self.create_guild_error.as_deref(),
+                    );
+                    if response.inner && !pending && !self.buffer.trim().is_empty() {
+                        self.create_guild_error = None;
+                        self.create_guild_request =
+                            Some(client.request(Request::CreateGuild(CreateGuild {
+                                name: self.buffer.trim().to_string(),
+                                icon_url: self.guild_image_buffer.clone(),
+                            })));
+                    }
+                    response.backdrop_response
                 }
-                CurrentModal::JoinModal => {
-                    unreachable!()
+                CurrentModal::Join => {
+                    let Some(client) = &mut self.client else {
+                        return;
+                    };
+                    let code = parse_invite(&self.join_code_buffer).map(str::to_string);
+                    let link = parse_message_link(&self.join_code_buffer);
+                    let preview = self
+                        .join_preview
+                        .as_ref()
+                        .filter(|preview| code.as_deref() == Some(preview.code.as_str()));
+                    let pending = [self.preview_request, self.join_request]
+                        .into_iter()
+                        .flatten()
+                        .any(|id| client.is_pending(id));
+                    let response = join_guild_modal(
+                        ctx,
+                        &mut self.join_code_buffer,
+                        preview,
+                        link.is_some(),
+                        pending,
+                        self.join_error.as_deref(),
+                    );
+                    match (response.inner, code, link) {
+                        (Some(JoinAction::Open), _, Some(link)) => {
+                            match self.open_message_link(link) {
+                                Ok(()) => {
+                                    self.show_current_modal = None;
+                                    self.join_code_buffer.clear();
+                                    self.join_error = None;
+                                }
+                                Err(err) => self.join_error = Some(err),
+                            }
+                        }
+                        (Some(JoinAction::Preview), Some(code), _) if !pending => {
+                            self.join_error = None;
+                            self.preview_request = Some(
+                                client.request(Request::PreviewInvite(PreviewInvite { code })),
+                            );
+                        }
+                        (Some(JoinAction::Join), Some(code), _) if !pending => {
+                            self.join_error = None;
+                            self.join_request =
+                                Some(client.request(Request::JoinGuild(JoinGuildByCode { code })));
+                        }
+                        _ => {}
+                    }
+                    response.backdrop_response
+                }
+                CurrentModal::Invite => {
+                    invite_modal(ctx, self.invite.as_ref(), self.invite_error.as_deref())
+                        .backdrop_response
                 }
             };
 
@@ -123,44 +456,206 @@ impl App {
     }
 
     fn panels(&mut self, ctx: &egui::Context) {
-        if let Some(select_guild) = GuildsPanel::new(&self.guilds, self.selected_guild).show(ctx) {
+        if let Some(client) = &mut self.client {
+            let status = match client.state() {
+                ClientState::Opened => None,
+                ClientState::Connecting | ClientState::Handshaking => {
+                    Some("Connecting...".to_string())
+                }
+                ClientState::Reconnecting {
+                    attempt,
+                    retry_at,
+                    reason,
+                    planned,
+                } => {
+                    let secs = retry_at
+                        .saturating_duration_since(Instant::now())
+                        .as_secs_f32()
+                        .ceil();
+                    ctx.request_repaint_after(Duration::from_secs(1));
+                    Some(match planned {
+                        true => format!("Reconnecting in {}s, {}", secs, reason),
+                        false => format!(
+                            "Connection lost ({}), reconnecting in {}s (attempt {})",
+                            reason, secs, attempt
+                        ),
+                    })
+                }
+                ClientState::Refused(_) => None,
+            };
+            if let Some(status) = status {
+                TopBottomPanel::top("connection status").show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(status);
+                        if matches!(client.state(), ClientState::Reconnecting { .. })
+                            && ui.button("Retry now").clicked()
+                        {
+                            client.reconnect_now();
+                        }
+                    });
+                });
+            }
+        }
+        if let Some(err) = &self.connection_error {
+            TopBottomPanel::top("connection error").show(ctx, |ui| {
+                ui.colored_label(ui.visuals().error_fg_color, err);
+            });
+        }
+
+        if let Some(select_guild) = GuildsPanel::new(&self.store.guilds, self.selected_guild)
+            .latency(self.client.as_ref().and_then(Client::rtt))
+            .show(ctx)
+        {
             match select_guild {
-                GuildsPanelResponse::Home => self.selected_guild = None,
-                GuildsPanelResponse::Guild(i) => self.selected_guild = Some(i),
+                GuildsPanelResponse::Home => {
+                    self.selected_guild = None;
+                    cancel_edit(&mut self.editing, &mut self.buffer);
+                    self.replying = None;
+                }
+                GuildsPanelResponse::Guild(i) => {
+                    self.selected_guild = Some(i);
+                    cancel_edit(&mut self.editing, &mut self.buffer);
+                    self.replying = None;
+                }
                 GuildsPanelResponse::New => {
-                    self.show_current_modal = Some(CurrentModal::CreateOrJoinModal);
+                    self.show_current_modal = Some(CurrentModal::CreateOrJoin);
                 }
             }
         }
 
         if let Some(guild_id) = self.selected_guild {
-            let guild = &mut self.guilds[guild_id];
+            let unread: HashSet<Snowflake> = self.store.guilds[guild_id]
+                .guild
+                .channels
+                .iter()
+                .filter(|channel| self.store.is_unread(channel))
+                .map(|channel| channel.id)
+                .collect();
+            let guild = &mut self.store.guilds[guild_id];
 
-            if let Some(ch) = ChannelsPanel::new(guild).show(ctx) {
-                guild.focused_channel_idx = ch;
+            if let Some(res) = ChannelsPanel::new(guild).unread(&unread).show(ctx) {
+                match res {
+                    ChannelsPanelResponse::Select(ch) => {
+                        guild.focused_channel_idx = ch;
+                        cancel_edit(&mut self.editing, &mut self.buffer);
+                        self.replying = None;
+                    }
+                    ChannelsPanelResponse::Invite => {
+                        if let Some(client) = &mut self.client {
+                            self.invite = None;
+                            self.invite_error = None;
+                            self.invite_request =
+                                Some(client.request(Request::CreateInvite(CreateInvite {
+                                    guild_id: guild.guild.id,
+                                    max_age_secs: Some(INVITE_MAX_AGE_SECS),
+                                    max_uses: None,
+                                })));
+                            self.show_current_modal = Some(CurrentModal::Invite);
+                        }
+                    }
+                }
             }
 
             if self.show_members {
-                MembersPanel::new(guild.members.values()).show(ctx);
+                MembersPanel::new(guild.guild.members.values()).show(ctx);
             }
 
-            if let Some(msg) = MessageBox::new(&mut self.buffer).show(ctx) {
+            let me = self.me.as_ref().map_or(0, |me| me.id);
+            let replying_to = self
+                .replying
+                .and_then(|replying| guild.message(replying.channel_id, replying.message_id))
+                .map(|msg| match guild.guild.members.get(&msg.author_id) {
+                    Some(author) => author.name.clone(),
+                    None => "Unknown user".to_string(),
+                });
+            if let Some(msg) = MessageBox::new(&mut self.buffer)
+                .editing(self.editing.is_some())
+                .replying(replying_to.as_deref())
+                .error(self.message_error.as_deref())
+                .show(ctx)
+            {
                 match msg {
+                    MessageBoxResponse::Send(msg) if self.editing.is_some() => {
+                        if let Some(client) = &mut self.client
+                            && let Some(MessageRef {
+                                channel_id,
+                                message_id,
+                            }) = self.editing.take()
+                        {
+                            // Like elsewhere, emptying a message while editing deletes it.
+                            if msg.trim().is_empty() {
+                                self.message_requests.insert(client.request(
+                                    Request::DeleteMessage(DeleteMessage {
+                                        channel_id,
+                                        message_id,
+                                    }),
+                                ));
+                            } else {
+                                self.message_requests
+                                    .insert(client.request(Request::EditMessage(EditMessage {
+                                        channel_id,
+                                        message_id,
+                                        content: msg,
+                                    })));
+                            }
+                        }
+                    }
                     MessageBoxResponse::Send(msg) => {
-                        // self.client.send.send(WsMessage::Text(msg));
-                        if let ChannelKind::Text(channel) =
-                            &mut guild.channels[guild.focused_channel_idx].kind
+                        if let Some(client) = &mut self.client
+                            && let Some(channel) =
+                                guild.guild.channels.get(guild.focused_channel_idx)
+                            && matches!(channel.kind, ChannelKind::Text(_))
+                            && !msg.trim().is_empty()
                         {
-                            channel.messages.push(Message {
-                                author_id: self.me,
+                            self.last_nonce = next_nonce(self.last_nonce);
+                            let reply_to = self.replying.take().map(|replying| replying.message_id);
+                            let request_id = client.request(Request::SendMessage(SendMessage {
+                                channel_id: channel.id,
+                                content: msg.clone(),
+                                nonce: self.last_nonce,
+                                reply_to,
+                            }));
+                            guild.pending_messages.push(PendingMessage {
+                                nonce: self.last_nonce,
+                                request_id,
+                                channel_id: channel.id,
                                 content: msg,
+                                reply_to,
+                                created_at: time::now_ms(),
+                                failed: false,
+                            });
+                        }
+                    }
+                    MessageBoxResponse::EditLast => {
+                        if let Some(Channel {
+                            id: channel_id,
+                            kind: ChannelKind::Text(text_channel),
+                            ..
+                        }) = guild.guild.channels.get(guild.focused_channel_idx)
+                            && let Some(last) = text_channel
+                                .messages
+                                .iter()
+                                .rev()
+                                .find(|msg| msg.author_id == me)
+                        {
+                            self.editing = Some(MessageRef {
+                                channel_id: *channel_id,
+                                message_id: last.id,
                             });
+                            self.replying = None;
+                            self.buffer = last.content.clone();
                         }
                     }
-                    MessageBoxResponse::Emoji => {
-                        self.me %= guild.members.len() as u32;
-                        self.me += 1;
+                    MessageBoxResponse::CancelEdit => {
+                        cancel_edit(&mut self.editing, &mut self.buffer);
+                    }
+                    MessageBoxResponse::CancelReply => {
+                        self.replying = None;
+                    }
+                    MessageBoxResponse::DismissError => {
+                        self.message_error = None;
                     }
+                    MessageBoxResponse::Emoji => {}
                     MessageBoxResponse::PickFile => {
                         if let Some(path) = rfd::FileDialog::new().pick_file() {
                             self.buffer = path.to_string_lossy().to_string();
@@ -169,27 +664,229 @@ impl App {
                 }
             }
 
-            if let Some(res) = AwesomeCentralPanel::new(guild).show(ctx) {
+            if let Some(res) = AwesomeCentralPanel::new(guild, me).show(ctx) {
                 match res {
                     AwesomePanelResponse::ToggleMemberList => {
                         self.show_members ^= true;
                     }
+                    AwesomePanelResponse::Message {
+                        channel_id,
+                        message_id,
+                        response,
+                    } => {
+                        let message = MessageRef {
+                            channel_id,
+                            message_id,
+                        };
+                        match response {
+                            MessageResponse::Reply => {
+                                cancel_edit(&mut self.editing, &mut self.buffer);
+                                self.replying = Some(message);
+                            }
+                            MessageResponse::Edit => {
+                                if let Some(msg) = guild.message(channel_id, message_id) {
+                                    self.replying = None;
+                                    self.editing = Some(message);
+                                    self.buffer = msg.content.clone();
+                                }
+                            }
+                            MessageResponse::CopyText => {
+                                if let Some(msg) = guild.message(channel_id, message_id) {
+                                    ctx.copy_text(msg.content.clone());
+                                }
+                            }
+                            MessageResponse::CopyLink => {
+                                ctx.copy_text(format!(
+                                    "http://{}/{}{}/{}/{}",
+                                    SERVER_URL,
+                                    MESSAGE_PATH,
+                                    guild.guild.id,
+                                    channel_id,
+                                    message_id
+                                ));
+                            }
+                            MessageResponse::ShowRevisions => {
+                                if let Some(client) = &mut self.client {
+                                    self.message_requests.insert(client.request(
+                                        Request::FetchMessageRevisions(FetchMessageRevisions {
+                                            channel_id,
+                                            message_id,
+                                        }),
+                                    ));
+                                }
+                            }
+                            MessageResponse::Delete => {
+                                if let Some(client) = &mut self.client {
+                                    self.message_requests.insert(client.request(
+                                        Request::DeleteMessage(DeleteMessage {
+                                            channel_id,
+                                            message_id,
+                                        }),
+                                    ));
+                                }
+                            }
+                            MessageResponse::Pin(pinned) => {
+                                if let Some(client) = &mut self.client {
+                                    self.message_requests.insert(client.request(
+                                        Request::PinMessage(PinMessage {
+                                            channel_id,
+                                            message_id,
+                                            pinned,
+                                        }),
+                                    ));
+                                }
+                            }
+                            MessageResponse::React(emoji) => {
+                                if let Some(client) = &mut self.client {
+                                    self.message_requests.insert(client.request(
+                                        Request::AddReaction(AddReaction {
+                                            channel_id,
+                                            message_id,
+                                            emoji,
+                                        }),
+                                    ));
+                                }
+                            }
+                        }
+                    }
+                    AwesomePanelResponse::LoadNewer(channel_id) => {
+                        let history = guild.history.entry(channel_id).or_default();
+                        if let Some(client) = &mut self.client
+                            && history.loading.is_none()
+                            && history.missing_newer
+                            && let Some(Channel {
+                                kind: ChannelKind::Text(text_channel),
+                                ..
+                            }) = guild.guild.channels.iter().find(|c| c.id == channel_id)
+                            && let Some(newest) = text_channel.messages.last()
+                        {
+                            history.loading =
+                                Some(client.request(Request::FetchMessages(FetchMessages {
+                                    channel_id,
+                                    cursor: MessageCursor::After(newest.id),
+                                    limit: MESSAGE_PAGE_SIZE,
+                                })));
+                        }
+                    }
+                    AwesomePanelResponse::LoadOlder(channel_id) => {
+                        let history = guild.history.entry(channel_id).or_default();
+                        if let Some(client) = &mut self.client
+                            && history.loading.is_none()
+                            && !history.reached_start
+                            && let Some(Channel {
+                                kind: ChannelKind::Text(text_channel),
+                                ..
+                            }) = guild.guild.channels.iter().find(|c| c.id == channel_id)
+                        {
+                            let cursor = match text_channel.messages.first() {
+                                Some(oldest) => MessageCursor::Before(oldest.id),
+                                None => MessageCursor::Latest,
+                            };
+                            history.loading =
+                                Some(client.request(Request::FetchMessages(FetchMessages {
+                                    channel_id,
+                                    cursor,
+                                    limit: MESSAGE_PAGE_SIZE,
+                                })));
+                        }
+                    }
                 }
             }
+
+            // The panel scrolled to the message jumped to if it was loaded, and once its channel
+            // is done loading it won't show up anymore.
+            let focused = guild.guild.channels.get(guild.focused_channel_idx);
+            if focused
+                .and_then(|channel| guild.history.get(&channel.id))
+                .is_none_or(|history| history.loading.is_none())
+            {
+                guild.jump_to = None;
+            }
+
+            if let Some(client) = &mut self.client
+                && let Some(Channel {
+                    id: channel_id,
+                    kind:
+                        ChannelKind::Text(TextChannel {
+                            last_message_id: Some(last_message_id),
+                            ..
+                        }),
+                    ..
+                }) = guild.guild.channels.get(guild.focused_channel_idx)
+                && self
+                    .store
+                    .read_states
+                    .get(channel_id)
+                    .is_none_or(|last_read_id| last_read_id < last_message_id)
+            {
+                self.store.read_states.insert(*channel_id, *last_message_id);
+                client.request(Request::MarkRead(MarkRead {
+                    channel_id: *channel_id,
+                    message_id: *last_message_id,
+                }));
+            }
         } else {
             CentralPanel::default().show(ctx, |ui| {
                 ui.add_sized(ui.available_size(), Label::new("Home"));
             });
         }
     }
+
+    /// Shows the message a link points to, fetching the history around it unless it is loaded.
+    fn open_message_link(&mut self, link: MessageLink) -> Result<(), String> {
+        let guild_idx = self
+            .store
+            .guild_index(link.guild_id)
+            .ok_or("You are not a member of that server")?;
+        let guild = &mut self.store.guilds[guild_idx];
+        let channel_idx = guild
+            .guild
+            .channels
+            .iter()
+            .position(|channel| channel.id == link.channel_id)
+            .ok_or("That channel no longer exists")?;
+        self.selected_guild = Some(guild_idx);
+        guild.focused_channel_idx = channel_idx;
+        guild.jump_to = Some(link.message_id);
+        cancel_edit(&mut self.editing, &mut self.buffer);
+        self.replying = None;
+        if guild.message(link.channel_id, link.message_id).is_none()
+            && let Some(client) = &mut self.client
+        {
+            let history = guild.history.entry(link.channel_id).or_default();
+            history.loading = Some(client.request(Request::FetchMessages(FetchMessages {
+                channel_id: link.channel_id,
+                cursor: MessageCursor::Around(link.message_id),
+                limit: MESSAGE_PAGE_SIZE,
+            })));
+        }
+        Ok(())
+    }
+}
+
+fn cancel_edit(editing: &mut Option<MessageRef>, buffer: &mut String) {
+    if editing.take().is_some() {
+        buffer.clear();
+    }
+}
+
+/// Nonces are timestamps in nanoseconds, bumped if the clock hasn't moved since the last one.
+fn next_nonce(last: Nonce) -> Nonce {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as Nonce);
+    now.max(last + 1)
 }
 
 fn create_guild_modal(
     ctx: &egui::Context,
     text: &mut String,
     guild_image_buffer: &mut String,
-) -> egui::ModalResponse<()> {
+    pending: bool,
+    error: Option<&str>,
+) -> egui::ModalResponse<bool> {
     Modal::new("create guild modal".into()).show(ctx, |ui| {
+        let mut create = false;
         ui.vertical(|ui| {
             ui.heading("Create a server");
             ui.separator();
@@ -199,7 +896,7 @@ fn create_guild_modal(
                     if ui.button("Pick image").clicked() {
                         let pic_path = rfd::FileDialog::new().pick_file();
                         if let Some(horse) = pic_path {
-                            *guild_image_buffer = horse.to_string_lossy().to_string();
+                            *guild_image_buffer = format!("file://{}", horse.to_string_lossy());
                         }
                     }
                     ui.label(guild_image_buffer.as_str());
@@ -209,7 +906,111 @@ fn create_guild_modal(
                 ui.text_edit_singleline(text); // CHANGE BUFFER LATAR
                 ui.label("Server description");
                 ui.text_edit_singleline(text); // CHANGE BUFFER LATAR
-            })
+            });
+            ui.separator();
+            if let Some(error) = error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            ui.vertical_centered_justified(|ui| {
+                create = ui
+                    .add_enabled(!pending, egui::Button::new("Create"))
+                    .clicked();
+            });
+        });
+        create
+    })
+}
+
+#[derive(Clone, Copy)]
+pub enum JoinAction {
+    Preview,
+    Join,
+    /// Go to the message the pasted message link points to.
+    Open,
+}
+
+fn join_guild_modal(
+    ctx: &egui::Context,
+    code: &mut String,
+    preview: Option<&InvitePreview>,
+    message_link: bool,
+    pending: bool,
+    error: Option<&str>,
+) -> ModalResponse<Option<JoinAction>> {
+    Modal::new("join guild modal".into()).show(ctx, |ui| {
+        let mut ret = None;
+        ui.vertical(|ui| {
+            ui.heading("Join a server");
+            ui.separator();
+            ui.label("Invite code, invite link or message link");
+            let input = ui.text_edit_singleline(code);
+            if input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                ret = Some(match message_link {
+                    true => JoinAction::Open,
+                    false => JoinAction::Preview,
+                });
+            }
+
+            if let Some(preview) = preview {
+                ui.horizontal(|ui| {
+                    ui.add_sized(egui::Vec2::splat(48.0), egui::Image::new(&preview.icon_url));
+                    ui.vertical(|ui| {
+                        ui.strong(&preview.guild_name);
+                        ui.weak(format!("{} members", preview.member_count));
+                    });
+                });
+            }
+            if let Some(error) = error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+
+            ui.separator();
+            ui.vertical_centered_justified(|ui| {
+                let valid = message_link || parse_invite(code).is_some();
+                let (label, action) = match preview {
+                    _ if message_link => ("Go to message", JoinAction::Open),
+                    Some(_) => ("Join", JoinAction::Join),
+                    None => ("Look up", JoinAction::Preview),
+                };
+                if ui
+                    .add_enabled(valid && !pending, egui::Button::new(label))
+                    .clicked()
+                {
+                    ret = Some(action);
+                }
+            });
+        });
+        ret
+    })
+}
+
+fn invite_modal(
+    ctx: &egui::Context,
+    invite: Option<&Invite>,
+    error: Option<&str>,
+) -> ModalResponse<()> {
+    Modal::new("invite modal".into()).show(ctx, |ui| {
+        ui.vertical(|ui| {
+            ui.heading("Invite friends");
+            ui.separator();
+            match (invite, error) {
+                (_, Some(error)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                (Some(invite), None) => {
+                    let link = format!("http://{}/{}{}", SERVER_URL, INVITE_PATH, invite.code);
+                    ui.horizontal(|ui| {
+                        ui.monospace(&link);
+                        if ui.button("Copy").clicked() {
+                            ui.ctx().copy_text(link.clone());
+                        }
+                    });
+                    ui.weak("Expires in 7 days");
+                }
+                (None, None) => {
+                    ui.spinner();
+                }
+            }
         });
     })
 }
@@ -1,77 +1,500 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::time::Duration;
+use std::time::Instant;
+
+use common::Ack;
 use common::ClientMessage;
+use common::Error;
+use common::ErrorCode;
+use common::Hello;
+use common::PROTOCOL_VERSION;
+use common::Refused;
+use common::Request;
+use common::RequestId;
+use common::Resume;
 use common::ServerMessage;
+use common::SessionId;
+use common::Welcome;
 use ewebsock::Options;
 use ewebsock::WsEvent;
 use ewebsock::WsMessage;
 use ewebsock::WsReceiver;
 use ewebsock::WsSender;
-use ewebsock::connect;
+use ewebsock::connect_with_wakeup;
+use rand::Rng;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Heartbeat intervals without hearing from the server after which the connection is presumed
+/// dead, even if the socket looks open.
+const MISSED_HEARTBEATS: u32 = 3;
+/// How long after a resume requests that were on the wire when the connection dropped may
+/// still be answered. The server may never have read them.
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub enum ClientState {
+    /// The websocket is being opened.
     Connecting,
+    /// The websocket is open and we are waiting for the server's [`common::Welcome`].
+    Handshaking,
     Opened,
-    Closed,
-    WsError(String),
+    /// The connection was lost, another attempt is made at `retry_at`. `planned` is set while
+    /// the server is restarting on purpose, which is not worth alarming anyone about.
+    Reconnecting {
+        attempt: u32,
+        retry_at: Instant,
+        reason: String,
+        planned: bool,
+    },
+    /// The server refused us, reconnecting would not help.
+    Refused(String),
 }
 
+/// A websocket connection to the server that reconnects by itself.
+///
+/// Messages sent while the connection is not [`ClientState::Opened`] are queued and flushed once
+/// the handshake completes. Requests the server rate limits are queued again and the queue is
+/// held back for as long as the server asked. After a reconnect the client resumes its session
+/// so the server replays whatever it missed. Requests that were already on the wire are failed
+/// with a synthetic [`ServerMessage::Error`] if the session can't be resumed, or if they are
+/// still unanswered [`REPLAY_TIMEOUT`] after the resume, so callers never wait on them forever.
 pub struct Client {
+    url: String,
+    token: String,
+    wake_up: egui::Context,
     recv: WsReceiver,
     sender: WsSender,
     state: ClientState,
+    attempt: u32,
+    session_id: Option<SessionId>,
+    last_seq: u64,
+    heartbeat_interval: Option<Duration>,
+    last_heard: Instant,
+    next_ping_nonce: u64,
+    ping: Option<(u64, Instant)>,
+    rtt: Option<Duration>,
+    /// Set by [`ServerMessage::Reconnect`] until the next handshake completes.
+    restart_reason: Option<String>,
+    outbound: VecDeque<ClientMessage>,
+    /// Rate limited requests, sent again before anything in `outbound`.
+    retry: VecDeque<ClientMessage>,
+    /// Set by [`ServerMessage::RateLimited`], nothing is sent from the queues until then.
+    paused_until: Option<Instant>,
+    inbound: VecDeque<ServerMessage>,
+    next_request_id: RequestId,
+    /// Requests not acked or rejected yet, kept to send them again if they are rate limited.
+    pending: HashMap<RequestId, Request>,
+    /// Requests that were on the wire when the connection was lost.
+    unanswered: HashSet<RequestId>,
+    /// Set on resume, when `unanswered` requests that are still pending get failed.
+    replay_deadline: Option<Instant>,
 }
 
 pub enum RecvError {
     Decoding,
     WsError(String),
+    Refused(String),
+}
+
+impl Display for RecvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecvError::Decoding => write!(f, "failed to decode server message"),
+            RecvError::WsError(err) => write!(f, "websocket error: {}", err),
+            RecvError::Refused(reason) => write!(f, "server refused the connection: {}", reason),
+        }
+    }
 }
 
 pub enum RecvResult {
     Ok(ServerMessage),
     OkNone,
     Connecting,
-    Disconnected,
     Error(RecvError),
 }
 
 impl Client {
-    pub fn new(url: impl Into<String>) -> Result<Self, String> {
-        let (sender, recv) = connect(url.into(), Options::default())?;
-        let state = ClientState::Connecting;
+    pub fn new(
+        url: impl Into<String>,
+        token: &str,
+        wake_up: egui::Context,
+    ) -> Result<Self, String> {
+        let url = url.into();
+        let (sender, recv) = open(&url, token, &wake_up)?;
         Ok(Self {
+            url,
+            token: token.to_string(),
+            wake_up,
             recv,
             sender,
-            state,
+            state: ClientState::Connecting,
+            attempt: 0,
+            session_id: None,
+            last_seq: 0,
+            heartbeat_interval: None,
+            last_heard: Instant::now(),
+            next_ping_nonce: 0,
+            ping: None,
+            rtt: None,
+            restart_reason: None,
+            outbound: VecDeque::new(),
+            retry: VecDeque::new(),
+            paused_until: None,
+            inbound: VecDeque::new(),
+            next_request_id: 0,
+            pending: HashMap::new(),
+            unanswered: HashSet::new(),
+            replay_deadline: None,
         })
     }
 
+    pub fn state(&self) -> &ClientState {
+        &self.state
+    }
+
+    /// Round-trip time of the last answered heartbeat, while the connection is open.
+    pub fn rtt(&self) -> Option<Duration> {
+        match self.state {
+            ClientState::Opened => self.rtt,
+            _ => None,
+        }
+    }
+
     pub fn recieve(&mut self) -> RecvResult {
-        match &self.state {
-            ClientState::Connecting => RecvResult::Connecting,
-            ClientState::Closed => RecvResult::Disconnected,
-            ClientState::WsError(err) => RecvResult::Error(RecvError::WsError(err.clone())),
-            ClientState::Opened => {
-                while let Some(msg) = self.recv.try_recv() {
-                    match msg {
-                        WsEvent::Opened => self.state = ClientState::Opened,
-                        WsEvent::Error(err) => self.state = ClientState::WsError(err),
-                        WsEvent::Closed => self.state = ClientState::Closed,
-                        WsEvent::Message(ws_message) => {
-                            if let WsMessage::Binary(data) = ws_message {
-                                let Ok(msg) = ServerMessage::decode(&data) else {
-                                    return RecvResult::Error(RecvError::Decoding);
-                                };
-                                return RecvResult::Ok(msg);
+        if let Some(msg) = self.inbound.pop_front() {
+            return RecvResult::Ok(msg);
+        }
+
+        if let ClientState::Reconnecting { retry_at, .. } = self.state
+            && Instant::now() >= retry_at
+        {
+            self.reconnect();
+        }
+
+        if self
+            .replay_deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.replay_deadline = None;
+            let unanswered = std::mem::take(&mut self.unanswered);
+            self.fail(unanswered, "no answer after reconnecting");
+        }
+
+        if self
+            .paused_until
+            .is_some_and(|paused_until| Instant::now() >= paused_until)
+        {
+            self.paused_until = None;
+            self.flush();
+        }
+
+        if let (ClientState::Opened, Some(interval)) = (&self.state, self.heartbeat_interval) {
+            let timeout = interval * MISSED_HEARTBEATS;
+            if self.last_heard.elapsed() > timeout {
+                self.connection_lost("the server stopped responding".to_string());
+            } else {
+                self.wake_up.request_repaint_after(interval);
+            }
+        }
+
+        while let Some(event) = self.recv.try_recv() {
+            match event {
+                WsEvent::Opened => {
+                    self.state = ClientState::Handshaking;
+                    match self.session_id {
+                        Some(session_id) => self.write(ClientMessage::Resume(Resume {
+                            session_id,
+                            last_seq: self.last_seq,
+                        })),
+                        None => self.write(hello()),
+                    }
+                }
+                WsEvent::Error(err) => {
+                    if self.connection_lost(err.clone()) {
+                        return RecvResult::Error(RecvError::WsError(err));
+                    }
+                }
+                WsEvent::Closed => {
+                    self.connection_lost("the server closed the connection".to_string());
+                }
+                WsEvent::Message(WsMessage::Binary(data)) => {
+                    self.last_heard = Instant::now();
+                    let msg = match ServerMessage::decode(&data) {
+                        Ok(ServerMessage::Sequenced { seq, message }) => {
+                            if seq <= self.last_seq {
+                                continue;
+                            }
+                            self.last_seq = seq;
+                            *message
+                        }
+                        Ok(ServerMessage::Heartbeat) => {
+                            self.next_ping_nonce = self.next_ping_nonce.wrapping_add(1);
+                            let nonce = self.next_ping_nonce;
+                            self.ping = Some((nonce, Instant::now()));
+                            self.write(ClientMessage::Ping { nonce });
+                            continue;
+                        }
+                        Ok(ServerMessage::Reconnect { reason }) => {
+                            // Everything queued for us was sent before this, so there is
+                            // nothing to wait for on this socket.
+                            self.restart_reason = Some(reason.clone());
+                            self.sender.close();
+                            self.connection_lost(reason);
+                            continue;
+                        }
+                        Ok(ServerMessage::Pong { nonce }) => {
+                            if let Some((sent_nonce, sent_at)) = self.ping
+                                && sent_nonce == nonce
+                            {
+                                self.rtt = Some(sent_at.elapsed());
+                                self.ping = None;
                             }
+                            continue;
                         }
+                        Ok(msg) => msg,
+                        Err(_) if matches!(self.state, ClientState::Handshaking) => {
+                            let reason = "the server speaks an incompatible protocol";
+                            self.state = ClientState::Refused(reason.to_string());
+                            return RecvResult::Error(RecvError::Refused(reason.to_string()));
+                        }
+                        Err(_) => return RecvResult::Error(RecvError::Decoding),
+                    };
+                    match &msg {
+                        ServerMessage::Welcome(Welcome {
+                            session_id,
+                            heartbeat_interval_ms,
+                            ..
+                        }) => {
+                            self.session_id = Some(*session_id);
+                            self.heartbeat_interval =
+                                Some(Duration::from_millis(*heartbeat_interval_ms as u64));
+                            self.last_seq = 0;
+                            self.opened();
+                        }
+                        ServerMessage::Resumed(_) => {
+                            self.replay_deadline = Some(Instant::now() + REPLAY_TIMEOUT);
+                            self.wake_up.request_repaint_after(REPLAY_TIMEOUT);
+                            self.opened();
+                        }
+                        ServerMessage::InvalidSession => {
+                            self.session_id = None;
+                            self.fail_in_flight();
+                            self.write(hello());
+                        }
+                        ServerMessage::RateLimited {
+                            request_id,
+                            retry_after_ms,
+                        } => {
+                            if let Some(request) = self.pending.get(request_id) {
+                                self.retry.push_back(ClientMessage::Request {
+                                    request_id: *request_id,
+                                    request: request.clone(),
+                                });
+                            }
+                            let retry_at =
+                                Instant::now() + Duration::from_millis(*retry_after_ms as u64);
+                            self.paused_until = self.paused_until.max(Some(retry_at));
+                            self.wake_up.request_repaint_after(Duration::from_millis(
+                                *retry_after_ms as u64,
+                            ));
+                            continue;
+                        }
+                        ServerMessage::Ack(Ack { request_id })
+                        | ServerMessage::Error(Error {
+                            request_id: Some(request_id),
+                            ..
+                        }) => {
+                            self.pending.remove(request_id);
+                        }
+                        ServerMessage::Refused(Refused { reason, .. }) => {
+                            self.state = ClientState::Refused(reason.clone());
+                            return RecvResult::Error(RecvError::Refused(reason.clone()));
+                        }
+                        _ => {}
                     }
+                    return RecvResult::Ok(msg);
                 }
-                RecvResult::OkNone
+                WsEvent::Message(_) => {}
             }
         }
+
+        if let Some(msg) = self.inbound.pop_front() {
+            return RecvResult::Ok(msg);
+        }
+        match &self.state {
+            ClientState::Opened => RecvResult::OkNone,
+            ClientState::Connecting
+            | ClientState::Handshaking
+            | ClientState::Reconnecting { .. } => RecvResult::Connecting,
+            ClientState::Refused(reason) => RecvResult::Error(RecvError::Refused(reason.clone())),
+        }
+    }
+
+    /// Skips the rest of the backoff and reconnects right away.
+    pub fn reconnect_now(&mut self) {
+        if matches!(self.state, ClientState::Reconnecting { .. }) {
+            self.reconnect();
+        }
+    }
+
+    /// Sends `request` and tracks it until the server acks or rejects it.
+    pub fn request(&mut self, request: Request) -> RequestId {
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        let request_id = self.next_request_id;
+        self.pending.insert(request_id, request.clone());
+        self.send(ClientMessage::Request {
+            request_id,
+            request,
+        });
+        request_id
     }
 
+    pub fn is_pending(&self, request_id: RequestId) -> bool {
+        self.pending.contains_key(&request_id)
+    }
+
+    /// Sends `msg` now if the connection is open and not held back by a rate limit, otherwise
+    /// queues it until then.
     pub fn send(&mut self, msg: ClientMessage) {
+        match self.state {
+            ClientState::Opened if self.paused_until.is_none() => self.write(msg),
+            _ => self.outbound.push_back(msg),
+        }
+    }
+
+    fn write(&mut self, msg: ClientMessage) {
         let bytes = msg.encode().expect("encoding error");
         self.sender.send(WsMessage::Binary(bytes));
     }
+
+    /// Moves to [`ClientState::Reconnecting`], returning `false` if the loss was already handled.
+    fn connection_lost(&mut self, reason: String) -> bool {
+        if matches!(
+            self.state,
+            ClientState::Reconnecting { .. } | ClientState::Refused(_)
+        ) {
+            return false;
+        }
+
+        self.ping = None;
+        self.rtt = None;
+
+        // Requests on the wire may be answered after a resume, but without a session they are
+        // lost.
+        self.replay_deadline = None;
+        match self.session_id {
+            Some(_) => self.unanswered.extend(self.in_flight()),
+            None => self.fail_in_flight(),
+        }
+
+        let delay = backoff(self.attempt);
+        self.attempt = self.attempt.saturating_add(1);
+        let planned = self.restart_reason.is_some();
+        self.state = ClientState::Reconnecting {
+            attempt: self.attempt,
+            retry_at: Instant::now() + delay,
+            reason: self.restart_reason.clone().unwrap_or(reason),
+            planned,
+        };
+        self.wake_up.request_repaint_after(delay);
+        true
+    }
+
+    fn opened(&mut self) {
+        self.state = ClientState::Opened;
+        self.attempt = 0;
+        self.restart_reason = None;
+        self.flush();
+    }
+
+    /// Writes out the queues, unless the connection isn't open or a rate limit holds them back.
+    fn flush(&mut self) {
+        if !matches!(self.state, ClientState::Opened) || self.paused_until.is_some() {
+            return;
+        }
+        while let Some(queued) = self.retry.pop_front().or_else(|| self.outbound.pop_front()) {
+            self.write(queued);
+        }
+    }
+
+    /// Fails every request that was sent but not answered yet.
+    fn fail_in_flight(&mut self) {
+        self.unanswered.clear();
+        let in_flight = self.in_flight();
+        self.fail(in_flight, "the connection was lost");
+    }
+
+    /// Requests that were written to a socket and not answered yet.
+    fn in_flight(&self) -> Vec<RequestId> {
+        let queued: HashSet<RequestId> = self
+            .retry
+            .iter()
+            .chain(&self.outbound)
+            .filter_map(|msg| match msg {
+                ClientMessage::Request { request_id, .. } => Some(*request_id),
+                _ => None,
+            })
+            .collect();
+        self.pending
+            .keys()
+            .filter(|request_id| !queued.contains(request_id))
+            .copied()
+            .collect()
+    }
+
+    /// Fails those of `request_ids` that are still pending with a synthetic error.
+    fn fail(&mut self, request_ids: impl IntoIterator<Item = RequestId>, message: &str) {
+        for request_id in request_ids {
+            if self.pending.remove(&request_id).is_some() {
+                self.inbound.push_back(ServerMessage::Error(Error {
+                    request_id: Some(request_id),
+                    code: ErrorCode::Internal,
+                    message: message.to_string(),
+                }));
+            }
+        }
+    }
+
+    fn reconnect(&mut self) {
+        match open(&self.url, &self.token, &self.wake_up) {
+            Ok((sender, recv)) => {
+                self.sender = sender;
+                self.recv = recv;
+                self.state = ClientState::Connecting;
+            }
+            Err(err) => {
+                self.state = ClientState::Connecting;
+                self.connection_lost(err);
+            }
+        }
+    }
+}
+
+fn open(url: &str, token: &str, wake_up: &egui::Context) -> Result<(WsSender, WsReceiver), String> {
+    let options = Options {
+        additional_headers: vec![("Authorization".to_string(), format!("Bearer {}", token))],
+        ..Options::default()
+    };
+    let wake_up = wake_up.clone();
+    connect_with_wakeup(url, options, move || wake_up.request_repaint())
+}
+
+fn hello() -> ClientMessage {
+    ClientMessage::Hello(Hello {
+        protocol_version: PROTOCOL_VERSION,
+        client_name: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        capabilities: Vec::new(),
+    })
+}
+
+/// Exponential backoff with jitter: somewhere between half and all of
+/// `INITIAL_BACKOFF * 2^attempt`, capped at `MAX_BACKOFF`.
+fn backoff(attempt: u32) -> Duration {
+    let delay = INITIAL_BACKOFF
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_BACKOFF);
+    delay.mul_f64(rand::rng().random_range(0.5..=1.0))
 }
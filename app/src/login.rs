@@ -0,0 +1,126 @@
+use std::sync::{Arc, Mutex};
+
+use common::{AuthResponse, Credentials, User};
+use egui::{Align, Button, CentralPanel, Layout, TextEdit};
+
+type PendingAuth = Arc<Mutex<Option<Result<AuthResponse, String>>>>;
+
+/// Login and registration form shown until the user has a session token.
+#[derive(Default)]
+pub struct LoginScreen {
+    username: String,
+    password: String,
+    error: Option<String>,
+    pending: Option<PendingAuth>,
+}
+
+#[derive(Clone, Copy)]
+enum AuthAction {
+    Login,
+    Register,
+}
+
+impl LoginScreen {
+    /// Returns the session token and the account once the server accepted the credentials.
+    pub fn show(&mut self, ctx: &egui::Context, server_url: &str) -> Option<(String, User)> {
+        if let Some(result) = self.poll() {
+            self.pending = None;
+            match result {
+                Ok(AuthResponse::Ok { token, user }) => {
+                    self.password.clear();
+                    self.error = None;
+                    return Some((token, user));
+                }
+                Ok(AuthResponse::Error { message, .. }) => self.error = Some(message),
+                Err(err) => self.error = Some(err),
+            }
+        }
+
+        let mut action = None;
+        CentralPanel::default().show(ctx, |ui| {
+            ui.with_layout(Layout::top_down(Align::Center), |ui| {
+                ui.set_max_width(256.0);
+                ui.add_space(ui.available_height() / 3.0);
+                ui.heading("Welcome back");
+                ui.separator();
+                ui.label("Username");
+                ui.add(TextEdit::singleline(&mut self.username));
+                ui.label("Password");
+                let password = ui.add(TextEdit::singleline(&mut self.password).password(true));
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+
+                let enabled = self.pending.is_none();
+                ui.vertical_centered_justified(|ui| {
+                    let submitted =
+                        password.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if ui.add_enabled(enabled, Button::new("Log in")).clicked()
+                        || (enabled && submitted)
+                    {
+                        action = Some(AuthAction::Login);
+                    }
+                    if ui.add_enabled(enabled, Button::new("Register")).clicked() {
+                        action = Some(AuthAction::Register);
+                    }
+                });
+            });
+        });
+
+        if let Some(action) = action {
+            self.submit(ctx, server_url, action);
+        }
+        None
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    fn poll(&mut self) -> Option<Result<AuthResponse, String>> {
+        self.pending.as_ref()?.lock().unwrap().take()
+    }
+
+    fn submit(&mut self, ctx: &egui::Context, server_url: &str, action: AuthAction) {
+        let path = match action {
+            AuthAction::Login => "login",
+            AuthAction::Register => "register",
+        };
+        let credentials = Credentials {
+            username: self.username.trim().to_string(),
+            password: self.password.clone(),
+        };
+        let body = match credentials.encode() {
+            Ok(body) => body,
+            Err(err) => {
+                self.error = Some(err.to_string());
+                return;
+            }
+        };
+
+        let mut request = ehttp::Request::post(format!("http://{}/{}", server_url, path), body);
+        request.headers = ehttp::Headers::new(&[
+            ("Accept", "*/*"),
+            ("Content-Type", "application/octet-stream"),
+        ]);
+
+        let pending = PendingAuth::default();
+        let slot = pending.clone();
+        let ctx = ctx.clone();
+        ehttp::fetch(request, move |result| {
+            let result = result.and_then(|response| {
+                AuthResponse::decode(&response.bytes).map_err(|_| {
+                    format!(
+                        "unexpected response: {} {}",
+                        response.status, response.status_text
+                    )
+                })
+            });
+            *slot.lock().unwrap() = Some(result);
+            ctx.request_repaint();
+        });
+
+        self.error = None;
+        self.pending = Some(pending);
+    }
+}
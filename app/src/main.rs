@@ -1,46 +1,84 @@
 mod app;
 mod client;
-mod mock;
+mod login;
 mod panels;
+mod store;
+mod time;
 mod widgets;
 
-pub struct Message {
-    pub author_id: u32,
-    pub content: String,
-}
+use std::collections::HashMap;
 
-pub struct TextChannel {
-    pub messages: Vec<Message>,
-}
+use common::ChannelKind;
+use common::Guild;
+use common::Message;
+use common::Nonce;
+use common::RequestId;
+use common::Snowflake;
+use eframe::NativeOptions;
 
-pub enum ChannelKind {
-    Text(TextChannel),
-    Voice,
+/// A guild as the client sees it: the shared model plus local UI state.
+pub struct GuildView {
+    pub guild: Guild,
+    pub focused_channel_idx: usize,
+    pub pending_messages: Vec<PendingMessage>,
+    pub history: HashMap<Snowflake, ChannelHistory>,
+    /// A message of the focused channel to scroll to, e.g. after opening a message link.
+    pub jump_to: Option<Snowflake>,
 }
 
-pub struct Channel {
-    pub name: String,
-    pub kind: ChannelKind,
-    pub description: String,
-}
+impl GuildView {
+    pub fn new(guild: Guild) -> Self {
+        Self {
+            guild,
+            focused_channel_idx: 0,
+            pending_messages: Vec::new(),
+            history: HashMap::new(),
+            jump_to: None,
+        }
+    }
 
-pub struct GuildMember {
-    pub name: String,
-    pub avatar_url: String,
+    /// Looks up a loaded message of a text channel.
+    pub fn message(&self, channel_id: Snowflake, message_id: Snowflake) -> Option<&Message> {
+        let channel = self.guild.channels.iter().find(|c| c.id == channel_id)?;
+        let ChannelKind::Text(text_channel) = &channel.kind else {
+            return None;
+        };
+        let messages = &text_channel.messages;
+        let i = messages
+            .binary_search_by_key(&message_id, |msg| msg.id)
+            .ok()?;
+        Some(&messages[i])
+    }
 }
 
-pub struct Guild {
-    pub id: u32,
-    pub name: String,
-    pub icon_url: String,
-    pub channels: Vec<Channel>,
-    pub members: HashMap<u32, GuildMember>,
-    pub focused_channel_idx: usize,
+/// How much of a text channel's history has been loaded, keyed by channel id in [`GuildView`].
+#[derive(Default)]
+pub struct ChannelHistory {
+    pub loading: Option<RequestId>,
+    pub reached_start: bool,
+    /// Set when a jump loaded messages far from the newest ones, until paging forward catches up.
+    pub missing_newer: bool,
 }
 
-use std::collections::HashMap;
+/// A message shown before the server confirmed it. It is replaced by the real message once
+/// a `MessageCreate` with the same nonce arrives, or marked as failed if the request errors.
+pub struct PendingMessage {
+    pub nonce: Nonce,
+    pub request_id: RequestId,
+    pub channel_id: Snowflake,
+    pub content: String,
+    pub reply_to: Option<Snowflake>,
+    /// When it was sent, in the same unit as `Message::created_at`.
+    pub created_at: i64,
+    pub failed: bool,
+}
 
-use eframe::NativeOptions;
+/// A message the message box refers to, when editing it or replying to it.
+#[derive(Clone, Copy)]
+pub struct MessageRef {
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+}
 
 fn main() {
     let native_options = NativeOptions::default();
@@ -1,16 +1,21 @@
-use super::Guild;
 use crate::{
-    ChannelKind, GuildMember,
-    widgets::{GuildButton, MessageWidget},
+    GuildView, time,
+    widgets::{
+        DaySeparator, GuildButton, MessagePermissions, MessageResponse, MessageState, MessageWidget,
+    },
 };
+use common::{ChannelKind, GuildMember, Message, Snowflake};
 use egui::{
-    Align, Button, CentralPanel, Frame, Key, KeyboardShortcut, Label, Layout, Modifiers,
+    Align, Button, CentralPanel, Frame, Key, KeyboardShortcut, Label, Layout, Modifiers, RichText,
     ScrollArea, SidePanel, TextBuffer, TextEdit, TopBottomPanel, Vec2,
 };
+use std::collections::HashSet;
+use std::time::Duration;
 
 pub struct GuildsPanel<'a> {
-    pub guilds: &'a [Guild],
+    pub guilds: &'a [GuildView],
     pub selected_guild: Option<usize>,
+    pub latency: Option<Duration>,
 }
 
 pub enum GuildsPanelResponse {
@@ -20,19 +25,36 @@ pub enum GuildsPanelResponse {
 }
 
 impl<'a> GuildsPanel<'a> {
-    pub fn new(guilds: &'a [Guild], selected_guild: Option<usize>) -> Self {
+    pub fn new(guilds: &'a [GuildView], selected_guild: Option<usize>) -> Self {
         Self {
             guilds,
             selected_guild,
+            latency: None,
         }
     }
 
+    /// Shows the round-trip time to the server at the bottom of the panel.
+    pub fn latency(mut self, latency: Option<Duration>) -> Self {
+        self.latency = latency;
+        self
+    }
+
     pub fn show(self, ctx: &egui::Context) -> Option<GuildsPanelResponse> {
         let mut ret = None;
         SidePanel::left("guilds")
             .resizable(false)
             .default_width(64.0)
             .show(ctx, |ui| {
+                if let Some(latency) = self.latency {
+                    TopBottomPanel::bottom("latency")
+                        .show_separator_line(false)
+                        .show_inside(ui, |ui| {
+                            ui.vertical_centered(|ui| {
+                                ui.weak(format!("{} ms", latency.as_millis()))
+                                    .on_hover_text("Round-trip time to the server");
+                            });
+                        });
+                }
                 ui.vertical_centered(|ui| {
                     ScrollArea::vertical().show(ui, |ui| {
                         let size = Vec2::splat(ui.available_width());
@@ -43,7 +65,7 @@ impl<'a> GuildsPanel<'a> {
                         for (i, guild) in self.guilds.iter().enumerate() {
                             if ui
                                 .add(
-                                    GuildButton::from_url(&guild.icon_url)
+                                    GuildButton::from_url(&guild.guild.icon_url)
                                         .selected(self.selected_guild == Some(i)),
                                 )
                                 .clicked()
@@ -64,21 +86,45 @@ impl<'a> GuildsPanel<'a> {
 
 pub enum AwesomePanelResponse {
     ToggleMemberList,
+    /// The message list was scrolled to the top of what has been loaded so far.
+    LoadOlder(Snowflake),
+    /// The message list was scrolled to the bottom while newer messages are still missing.
+    LoadNewer(Snowflake),
+    /// Something was done to a message, e.g. through its menu.
+    Message {
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        response: MessageResponse,
+    },
 }
 
+/// Distance from either end of the message list, in points, at which more messages get loaded.
+const LOAD_MORE_THRESHOLD: f32 = 64.0;
+
 pub struct AwesomeCentralPanel<'a> {
-    guild: &'a Guild,
+    guild: &'a GuildView,
+    me: Snowflake,
 }
 
 impl<'a> AwesomeCentralPanel<'a> {
-    pub fn new(guild: &'a Guild) -> Self {
-        Self { guild }
+    pub fn new(guild: &'a GuildView, me: Snowflake) -> Self {
+        Self { guild, me }
     }
 
     pub fn show(self, ctx: &egui::Context) -> Option<AwesomePanelResponse> {
-        let channel = &self.guild.channels[self.guild.focused_channel_idx];
-        let members = &self.guild.members;
+        let members = &self.guild.guild.members;
         let mut ret = None;
+        let Some(channel) = self
+            .guild
+            .guild
+            .channels
+            .get(self.guild.focused_channel_idx)
+        else {
+            CentralPanel::default().show(ctx, |ui| {
+                ui.add_sized(ui.available_size(), Label::new("No channels"));
+            });
+            return ret;
+        };
         CentralPanel::default().show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.heading(&channel.name);
@@ -96,14 +142,118 @@ impl<'a> AwesomeCentralPanel<'a> {
             });
             ui.separator();
             match channel.kind {
-                ChannelKind::Text(ref channel) => {
-                    ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
-                        for msg in &channel.messages {
-                            let author = members.get(&msg.author_id).unwrap();
-                            ui.add(MessageWidget::new(msg, author));
-                            ui.spacing();
-                        }
-                    });
+                ChannelKind::Text(ref text_channel) => {
+                    let unknown = GuildMember {
+                        name: "Unknown user".to_string(),
+                        avatar_url: String::new(),
+                    };
+                    let output = ScrollArea::vertical()
+                        .id_salt(("messages", channel.id))
+                        .stick_to_bottom(true)
+                        .show(ui, |ui| {
+                            let moderator = self.guild.guild.is_moderator(self.me);
+                            let mut previous = None;
+                            let reply = |msg: &Message| {
+                                let replied = self.guild.message(channel.id, msg.reply_to?)?;
+                                let author = members.get(&replied.author_id).unwrap_or(&unknown);
+                                Some((replied, author))
+                            };
+                            for msg in &text_channel.messages {
+                                let author = members.get(&msg.author_id).unwrap_or(&unknown);
+                                let grouped = separate(ui, previous, msg);
+                                if self.guild.jump_to == Some(msg.id) {
+                                    ui.scroll_to_cursor(Some(Align::Center));
+                                }
+                                let permissions = MessagePermissions {
+                                    edit: msg.author_id == self.me,
+                                    delete: msg.author_id == self.me || moderator,
+                                    pin: moderator,
+                                };
+                                let response = MessageWidget::new(msg, author)
+                                    .grouped(grouped)
+                                    .revisions(moderator)
+                                    .actions(self.me, permissions)
+                                    .reply(reply(msg))
+                                    .show(ui);
+                                if let Some(response) = response {
+                                    ret = Some(AwesomePanelResponse::Message {
+                                        channel_id: channel.id,
+                                        message_id: msg.id,
+                                        response,
+                                    });
+                                }
+                                ui.spacing();
+                                previous = Some(msg);
+                            }
+
+                            let pending = self
+                                .guild
+                                .pending_messages
+                                .iter()
+                                .filter(|pending| pending.channel_id == channel.id);
+                            let mut previous = previous.cloned();
+                            for pending in pending {
+                                let msg = Message {
+                                    id: 0,
+                                    author_id: self.me,
+                                    content: pending.content.clone(),
+                                    created_at: pending.created_at,
+                                    edited_at: None,
+                                    reply_to: pending.reply_to,
+                                    pinned: false,
+                                    reactions: Vec::new(),
+                                };
+                                let author = members.get(&self.me).unwrap_or(&unknown);
+                                let state = if pending.failed {
+                                    MessageState::Failed
+                                } else {
+                                    MessageState::Pending
+                                };
+                                let grouped = separate(ui, previous.as_ref(), &msg);
+                                MessageWidget::new(&msg, author)
+                                    .state(state)
+                                    .grouped(grouped)
+                                    .reply(reply(&msg))
+                                    .show(ui);
+                                ui.spacing();
+                                previous = Some(msg);
+                            }
+                        });
+
+                    // When older messages get prepended, shift the view by the height they
+                    // added so the messages the user was looking at stay in place, unless the
+                    // view is moving to a message jumped to anyway.
+                    let anchor_id = output.id.with("anchor");
+                    let first_id = text_channel.messages.first().map(|msg| msg.id);
+                    let previous: Option<(Snowflake, f32)> = ui.data(|d| d.get_temp(anchor_id));
+                    if let (Some((previous_first, previous_height)), Some(first_id)) =
+                        (previous, first_id)
+                        && first_id < previous_first
+                        && self.guild.jump_to.is_none()
+                    {
+                        let mut state = output.state;
+                        state.offset.y += output.content_size.y - previous_height;
+                        state.store(ui.ctx(), output.id);
+                        ui.ctx().request_repaint();
+                    }
+                    if let Some(first_id) = first_id {
+                        ui.data_mut(|d| {
+                            d.insert_temp(anchor_id, (first_id, output.content_size.y))
+                        });
+                    }
+
+                    let below =
+                        output.content_size.y - output.state.offset.y - output.inner_rect.height();
+                    let missing_newer = self
+                        .guild
+                        .history
+                        .get(&channel.id)
+                        .is_some_and(|history| history.missing_newer);
+                    if output.state.offset.y < LOAD_MORE_THRESHOLD {
+                        ret = Some(AwesomePanelResponse::LoadOlder(channel.id));
+                    } else if missing_newer && below < LOAD_MORE_THRESHOLD {
+                        ret = Some(AwesomePanelResponse::LoadNewer(channel.id));
+                    }
                 }
                 ChannelKind::Voice => {
                     ui.add_sized(ui.available_size(), Label::new("Voice"));
@@ -115,6 +265,21 @@ impl<'a> AwesomeCentralPanel<'a> {
     }
 }
 
+/// Adds a day separator above `msg` if it was posted on another day than `previous`, and
+/// returns whether `msg` continues the group of `previous`. Replies always start a new group.
+fn separate(ui: &mut egui::Ui, previous: Option<&Message>, msg: &Message) -> bool {
+    let date = time::local_date(msg.created_at);
+    if previous.is_none_or(|previous| time::local_date(previous.created_at) != date) {
+        ui.add(DaySeparator::new(date));
+        return false;
+    }
+    previous.is_some_and(|previous| {
+        msg.reply_to.is_none()
+            && previous.author_id == msg.author_id
+            && msg.created_at - previous.created_at < time::GROUP_WINDOW_MS
+    })
+}
+
 pub struct MembersPanel<T>(T);
 
 impl<'a, I: IntoIterator<Item = &'a GuildMember>> MembersPanel<I> {
@@ -145,15 +310,46 @@ pub enum MessageBoxResponse {
     Send(String),
     PickFile,
     Emoji,
+    /// Up was pressed in the empty box, to edit the user's last message.
+    EditLast,
+    CancelEdit,
+    CancelReply,
+    DismissError,
 }
 
 pub struct MessageBox<'a> {
     buffer: &'a mut String,
+    editing: bool,
+    replying: Option<&'a str>,
+    error: Option<&'a str>,
 }
 
 impl<'a> MessageBox<'a> {
     pub fn new(buffer: &'a mut String) -> Self {
-        Self { buffer }
+        Self {
+            buffer,
+            editing: false,
+            replying: None,
+            error: None,
+        }
+    }
+
+    /// The buffer holds a message being edited rather than a new one.
+    pub fn editing(mut self, editing: bool) -> Self {
+        self.editing = editing;
+        self
+    }
+
+    /// The message will be a reply to one by the named author.
+    pub fn replying(mut self, author: Option<&'a str>) -> Self {
+        self.replying = author;
+        self
+    }
+
+    /// Why the last action on a message failed, shown until dismissed.
+    pub fn error(mut self, error: Option<&'a str>) -> Self {
+        self.error = error;
+        self
     }
 
     pub fn show(self, ctx: &egui::Context) -> Option<MessageBoxResponse> {
@@ -175,12 +371,27 @@ impl<'a> MessageBox<'a> {
                     }
 
                     let send = ui.add_sized(size, Button::new(""));
-                    ui.add_sized(
+                    let edit = ui.add_sized(
                         ui.available_size(),
                         TextEdit::multiline(self.buffer)
                             .return_key(Some(KeyboardShortcut::new(Modifiers::CTRL, Key::Enter))),
                     );
 
+                    if edit.has_focus()
+                        && self.buffer.is_empty()
+                        && !self.editing
+                        && ui.input(|i| i.key_pressed(Key::ArrowUp))
+                    {
+                        ret = Some(MessageBoxResponse::EditLast);
+                    }
+                    if ui.input(|i| i.key_pressed(Key::Escape)) {
+                        if self.editing {
+                            ret = Some(MessageBoxResponse::CancelEdit);
+                        } else if self.replying.is_some() {
+                            ret = Some(MessageBoxResponse::CancelReply);
+                        }
+                    }
+
                     if ui.input(|i| i.key_pressed(Key::Enter) && !i.modifiers.ctrl)
                         || send.clicked()
                     {
@@ -188,20 +399,64 @@ impl<'a> MessageBox<'a> {
                     }
                 });
             });
+        if self.editing {
+            TopBottomPanel::bottom("editing").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.weak("Editing message. Escape to cancel, empty it to delete.");
+                    if ui.small_button("Cancel").clicked() {
+                        ret = Some(MessageBoxResponse::CancelEdit);
+                    }
+                });
+            });
+        } else if let Some(author) = self.replying {
+            TopBottomPanel::bottom("replying").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.weak(format!("Replying to {}. Escape to cancel.", author));
+                    if ui.small_button("Cancel").clicked() {
+                        ret = Some(MessageBoxResponse::CancelReply);
+                    }
+                });
+            });
+        }
+        if let Some(error) = self.error {
+            TopBottomPanel::bottom("message error").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                    if ui.small_button("Dismiss").clicked() {
+                        ret = Some(MessageBoxResponse::DismissError);
+                    }
+                });
+            });
+        }
         ret
     }
 }
 
+pub enum ChannelsPanelResponse {
+    Select(usize),
+    Invite,
+}
+
 pub struct ChannelsPanel<'a> {
-    guild: &'a Guild,
+    guild: &'a GuildView,
+    unread: Option<&'a HashSet<Snowflake>>,
 }
 
 impl<'a> ChannelsPanel<'a> {
-    pub fn new(guild: &'a Guild) -> Self {
-        Self { guild }
+    pub fn new(guild: &'a GuildView) -> Self {
+        Self {
+            guild,
+            unread: None,
+        }
+    }
+
+    /// Highlights the channels with ids in `unread`.
+    pub fn unread(mut self, unread: &'a HashSet<Snowflake>) -> Self {
+        self.unread = Some(unread);
+        self
     }
 
-    pub fn show(self, ctx: &egui::Context) -> Option<usize> {
+    pub fn show(self, ctx: &egui::Context) -> Option<ChannelsPanelResponse> {
         let mut ret = None;
         SidePanel::left("channels")
             .resizable(false)
@@ -209,26 +464,32 @@ impl<'a> ChannelsPanel<'a> {
             .show(ctx, |ui| {
                 ui.vertical(|ui| {
                     ui.horizontal(|ui| {
-                        ui.heading(&self.guild.name);
+                        ui.heading(&self.guild.guild.name);
                         ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
-                            ui.button("");
+                            if ui.button("").on_hover_text("Invite people").clicked() {
+                                ret = Some(ChannelsPanelResponse::Invite);
+                            }
                         });
                     });
                     ui.separator();
                     ScrollArea::vertical().show(ui, |ui| {
-                        for (i, channel) in self.guild.channels.iter().enumerate() {
+                        for (i, channel) in self.guild.guild.channels.iter().enumerate() {
                             let icon = match channel.kind {
                                 ChannelKind::Text(_) => "",
                                 ChannelKind::Voice => "",
                             };
+                            let mut label = RichText::new(format!("{} {}", icon, channel.name));
+                            if self
+                                .unread
+                                .is_some_and(|unread| unread.contains(&channel.id))
+                            {
+                                label = label.strong();
+                            }
                             if ui
-                                .selectable_label(
-                                    i == self.guild.focused_channel_idx,
-                                    format!("{} {}", icon, channel.name),
-                                )
+                                .selectable_label(i == self.guild.focused_channel_idx, label)
                                 .clicked()
                             {
-                                ret = Some(i);
+                                ret = Some(ChannelsPanelResponse::Select(i));
                             }
                         }
                     })
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use common::Channel;
+use common::ChannelKind;
+use common::Guild;
+use common::MemberJoin;
+use common::Message;
+use common::MessageCreate;
+use common::MessageCursor;
+use common::MessageDelete;
+use common::MessageUpdate;
+use common::Messages;
+use common::ReactionAdd;
+use common::ReadState;
+use common::Ready;
+use common::Snowflake;
+use common::TextChannel;
+
+use super::GuildView;
+
+/// Messages requested per page of channel history.
+pub const MESSAGE_PAGE_SIZE: u32 = 50;
+
+/// The client's copy of the server state. It is rebuilt from every [`Ready`] and kept up to
+/// date by applying the events that follow as deltas.
+#[derive(Default)]
+pub struct Store {
+    pub guilds: Vec<GuildView>,
+    /// Id of the last read message, keyed by text channel id.
+    pub read_states: HashMap<Snowflake, Snowflake>,
+}
+
+impl Store {
+    /// Replaces everything with the snapshot in `ready`.
+    pub fn load(&mut self, ready: Ready) {
+        self.guilds = ready.guilds.into_iter().map(GuildView::new).collect();
+        self.read_states = ready
+            .read_states
+            .into_iter()
+            .map(|state| (state.channel_id, state.last_read_id))
+            .collect();
+    }
+
+    pub fn guild_index(&self, guild_id: Snowflake) -> Option<usize> {
+        self.guilds
+            .iter()
+            .position(|view| view.guild.id == guild_id)
+    }
+
+    pub fn guild_mut(&mut self, guild_id: Snowflake) -> Option<&mut GuildView> {
+        self.guilds
+            .iter_mut()
+            .find(|view| view.guild.id == guild_id)
+    }
+
+    /// Adds `guild`, or replaces it if it is already known, and returns its index.
+    pub fn join_guild(&mut self, guild: Guild) -> usize {
+        let view = GuildView::new(guild);
+        match self.guild_index(view.guild.id) {
+            Some(i) => {
+                self.guilds[i] = view;
+                i
+            }
+            None => {
+                self.guilds.push(view);
+                self.guilds.len() - 1
+            }
+        }
+    }
+
+    pub fn member_join(&mut self, member_join: MemberJoin) {
+        if let Some(view) = self.guild_mut(member_join.guild_id) {
+            view.guild
+                .members
+                .insert(member_join.user_id, member_join.member);
+        }
+    }
+
+    /// Merges a page of history into its channel.
+    pub fn messages(&mut self, page: Messages) {
+        let Some(view) = self.guild_mut(page.guild_id) else {
+            return;
+        };
+        let history = view.history.entry(page.channel_id).or_default();
+        history.loading = None;
+        let partial = page.messages.len() < MESSAGE_PAGE_SIZE as usize;
+        match page.cursor {
+            MessageCursor::Latest | MessageCursor::Before(_) if partial => {
+                history.reached_start = true;
+            }
+            MessageCursor::After(_) if partial => history.missing_newer = false,
+            _ => {}
+        }
+        let Some(text_channel) = text_channel_mut(view, page.channel_id) else {
+            return;
+        };
+        let span = |messages: &[Message]| Some((messages.first()?.id, messages.last()?.id));
+        // A page jumped to that doesn't touch the loaded messages would leave a hole between
+        // them, so it replaces them and the rest is paged in from there instead.
+        if let MessageCursor::Around(_) = page.cursor
+            && let Some((first, last)) = span(&page.messages)
+            && span(&text_channel.messages).is_none_or(|(loaded_first, loaded_last)| {
+                last < loaded_first || first > loaded_last
+            })
+        {
+            let missing_newer = text_channel.last_message_id > Some(last);
+            text_channel.messages = page.messages;
+            let history = view.history.entry(page.channel_id).or_default();
+            history.reached_start = false;
+            history.missing_newer = missing_newer;
+            return;
+        }
+        text_channel.messages.extend(page.messages);
+        text_channel.messages.sort_by_key(|msg| msg.id);
+        text_channel.messages.dedup_by_key(|msg| msg.id);
+    }
+
+    pub fn message_create(&mut self, message_create: MessageCreate) {
+        let Some(view) = self.guild_mut(message_create.guild_id) else {
+            return;
+        };
+        if let Some(nonce) = message_create.nonce {
+            view.pending_messages
+                .retain(|pending| pending.nonce != nonce);
+        }
+        // Paging forward picks it up once it gets there.
+        let missing_newer = view
+            .history
+            .get(&message_create.channel_id)
+            .is_some_and(|history| history.missing_newer);
+        if let Some(text_channel) = text_channel_mut(view, message_create.channel_id) {
+            let id = message_create.message.id;
+            text_channel.last_message_id = text_channel.last_message_id.max(Some(id));
+            // Events can be published out of id order, and a page of history may already have
+            // brought the message along.
+            let i = text_channel.messages.partition_point(|msg| msg.id < id);
+            if !missing_newer && text_channel.messages.get(i).is_none_or(|msg| msg.id != id) {
+                text_channel.messages.insert(i, message_create.message);
+            }
+        }
+    }
+
+    pub fn message_update(&mut self, message_update: MessageUpdate) {
+        let Some(view) = self.guild_mut(message_update.guild_id) else {
+            return;
+        };
+        if let Some(text_channel) = text_channel_mut(view, message_update.channel_id)
+            && let Some(message) = text_channel
+                .messages
+                .iter_mut()
+                .find(|message| message.id == message_update.message.id)
+        {
+            *message = message_update.message;
+        }
+    }
+
+    pub fn message_delete(&mut self, message_delete: MessageDelete) {
+        let Some(view) = self.guild_mut(message_delete.guild_id) else {
+            return;
+        };
+        if let Some(text_channel) = text_channel_mut(view, message_delete.channel_id) {
+            text_channel
+                .messages
+                .retain(|message| message.id != message_delete.message_id);
+            // Without the deleted message, the newest one we know of is the best guess.
+            if text_channel.last_message_id == Some(message_delete.message_id) {
+                text_channel.last_message_id = text_channel.messages.last().map(|msg| msg.id);
+            }
+        }
+    }
+
+    pub fn reaction_add(&mut self, reaction_add: ReactionAdd) {
+        let Some(view) = self.guild_mut(reaction_add.guild_id) else {
+            return;
+        };
+        if let Some(text_channel) = text_channel_mut(view, reaction_add.channel_id)
+            && let Some(message) = text_channel
+                .messages
+                .iter_mut()
+                .find(|message| message.id == reaction_add.message_id)
+        {
+            message.add_reaction(&reaction_add.emoji, reaction_add.user_id);
+        }
+    }
+
+    pub fn read_state(&mut self, state: ReadState) {
+        let last_read_id = self.read_states.entry(state.channel_id).or_default();
+        *last_read_id = (*last_read_id).max(state.last_read_id);
+    }
+
+    pub fn is_unread(&self, channel: &Channel) -> bool {
+        let ChannelKind::Text(TextChannel {
+            last_message_id: Some(last_message_id),
+            ..
+        }) = channel.kind
+        else {
+            return false;
+        };
+        self.read_states
+            .get(&channel.id)
+            .is_none_or(|&last_read_id| last_read_id < last_message_id)
+    }
+}
+
+fn text_channel_mut(view: &mut GuildView, channel_id: Snowflake) -> Option<&mut TextChannel> {
+    view.guild
+        .channels
+        .iter_mut()
+        .find_map(|channel| match &mut channel.kind {
+            ChannelKind::Text(text_channel) if channel.id == channel_id => Some(text_channel),
+            _ => None,
+        })
+}
@@ -0,0 +1,62 @@
+use chrono::{DateTime, Days, Local, NaiveDate, TimeZone, Utc};
+
+/// Consecutive messages of the same author are shown under one header if each follows the
+/// previous one within this many milliseconds.
+pub const GROUP_WINDOW_MS: i64 = 7 * 60 * 1000;
+
+/// Unix timestamp in milliseconds, as carried by `common::Message`.
+pub fn now_ms() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+fn local(timestamp_ms: i64) -> DateTime<Local> {
+    Local
+        .timestamp_millis_opt(timestamp_ms)
+        .earliest()
+        .unwrap_or_else(|| DateTime::<Utc>::UNIX_EPOCH.with_timezone(&Local))
+}
+
+/// The local calendar day of a timestamp.
+pub fn local_date(timestamp_ms: i64) -> NaiveDate {
+    local(timestamp_ms).date_naive()
+}
+
+/// "Today" or "Yesterday" if `date` is one of them as seen from `today`.
+fn relative_day(date: NaiveDate, today: NaiveDate) -> Option<&'static str> {
+    if date == today {
+        Some("Today")
+    } else if today.checked_sub_days(Days::new(1)) == Some(date) {
+        Some("Yesterday")
+    } else {
+        None
+    }
+}
+
+/// Label of a day separator, e.g. "Today" or "Monday, March 3, 2025".
+pub fn day_label(date: NaiveDate) -> String {
+    match relative_day(date, Local::now().date_naive()) {
+        Some(day) => day.to_string(),
+        None => date.format("%A, %B %-d, %Y").to_string(),
+    }
+}
+
+/// Shown next to a message header, e.g. "Today at 14:05" or "03/03/2025 14:05".
+pub fn message_time(timestamp_ms: i64) -> String {
+    let time = local(timestamp_ms);
+    match relative_day(time.date_naive(), Local::now().date_naive()) {
+        Some(day) => format!("{} at {}", day, time.format("%H:%M")),
+        None => time.format("%d/%m/%Y %H:%M").to_string(),
+    }
+}
+
+/// Time of day only, for messages grouped under an earlier header.
+pub fn short_time(timestamp_ms: i64) -> String {
+    local(timestamp_ms).format("%H:%M").to_string()
+}
+
+/// Full date and time, for tooltips.
+pub fn full_time(timestamp_ms: i64) -> String {
+    local(timestamp_ms)
+        .format("%A, %B %-d, %Y %H:%M:%S")
+        .to_string()
+}
@@ -1,5 +1,15 @@
+use chrono::NaiveDate;
 use egui::Align;
+use egui::Align2;
+use egui::Button;
 use egui::ImageButton;
+use egui::Label;
+use egui::RichText;
+use egui::Sense;
+use egui::TextStyle;
+use egui::TextWrapMode;
+use egui::UiBuilder;
+use egui::WidgetText;
 
 use egui::Layout;
 use egui::Vec2;
@@ -8,8 +18,11 @@ use egui::Widget;
 
 use egui::Image;
 
-use crate::GuildMember;
-use crate::Message;
+use common::GuildMember;
+use common::Message;
+use common::Snowflake;
+
+use crate::time;
 
 pub struct GuildButton<'a>(Image<'a>, bool);
 
@@ -35,33 +48,311 @@ impl Widget for GuildButton<'_> {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MessageState {
+    Sent,
+    Pending,
+    Failed,
+}
+
+/// Width of the avatar column, which grouped messages leave empty.
+const AVATAR_SIZE: f32 = 32.0;
+
+/// Offered under "Add reaction" in the message menu.
+const QUICK_REACTIONS: [&str; 8] = ["👍", "👎", "😂", "❤", "🎉", "😮", "😢", "👀"];
+
+pub enum MessageResponse {
+    /// The "(edited)" marker was clicked.
+    ShowRevisions,
+    Reply,
+    Edit,
+    Delete,
+    CopyText,
+    CopyLink,
+    /// Pin the message, or unpin it if `false`.
+    Pin(bool),
+    React(String),
+}
+
+/// What the user may do with a message beyond replying, reacting and copying it.
+#[derive(Clone, Copy, Default)]
+pub struct MessagePermissions {
+    pub edit: bool,
+    pub delete: bool,
+    pub pin: bool,
+}
+
 pub struct MessageWidget<'a> {
     msg: &'a Message,
     author: &'a GuildMember,
+    state: MessageState,
+    grouped: bool,
+    revisions: bool,
+    actions: Option<(Snowflake, MessagePermissions)>,
+    reply: Option<(&'a Message, &'a GuildMember)>,
 }
 
 impl<'a> MessageWidget<'a> {
     pub fn new(msg: &'a Message, author: &'a GuildMember) -> Self {
-        Self { msg, author }
+        Self {
+            msg,
+            author,
+            state: MessageState::Sent,
+            grouped: false,
+            revisions: false,
+            actions: None,
+            reply: None,
+        }
     }
-}
 
-impl<'a> Widget for MessageWidget<'a> {
-    fn ui(self, ui: &mut egui::Ui) -> egui::Response {
+    pub fn state(mut self, state: MessageState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Continues the previous message's group: no avatar or name, and the time of day only
+    /// shows while hovered.
+    pub fn grouped(mut self, grouped: bool) -> Self {
+        self.grouped = grouped;
+        self
+    }
+
+    /// Lets the "(edited)" marker be clicked to view the message's earlier versions.
+    pub fn revisions(mut self, revisions: bool) -> Self {
+        self.revisions = revisions;
+        self
+    }
+
+    /// Enables the message menu and reacting, as `me` with `permissions`.
+    pub fn actions(mut self, me: Snowflake, permissions: MessagePermissions) -> Self {
+        self.actions = Some((me, permissions));
+        self
+    }
+
+    /// The message this one replies to, with its author. Replies without one are shown as
+    /// replying to a message that is not available.
+    pub fn reply(mut self, reply: Option<(&'a Message, &'a GuildMember)>) -> Self {
+        self.reply = reply;
+        self
+    }
+
+    pub fn show(self, ui: &mut egui::Ui) -> Option<MessageResponse> {
+        let inner = ui.scope_builder(UiBuilder::new().sense(Sense::click()), |ui| {
+            self.message(ui)
+        });
+        let mut ret = inner.inner;
+        inner.response.context_menu(|ui| {
+            if let Some(action) = self.menu(ui) {
+                ret = Some(action);
+            }
+        });
+        ret
+    }
+
+    fn message(&self, ui: &mut egui::Ui) -> Option<MessageResponse> {
+        if self.grouped {
+            let inner = ui.horizontal(|ui| {
+                ui.add_space(AVATAR_SIZE + ui.spacing().item_spacing.x);
+                ui.vertical(|ui| self.content(ui)).inner
+            });
+            if inner.response.contains_pointer() {
+                ui.painter().text(
+                    inner.response.rect.left_top(),
+                    Align2::LEFT_TOP,
+                    time::short_time(self.msg.created_at),
+                    TextStyle::Small.resolve(ui.style()),
+                    ui.visuals().weak_text_color(),
+                );
+            }
+            return inner.inner;
+        }
+
+        if self.msg.reply_to.is_some() {
+            ui.horizontal(|ui| {
+                ui.add_space(AVATAR_SIZE + ui.spacing().item_spacing.x);
+                let text = match self.reply {
+                    Some((msg, author)) => format!(" {}: {}", author.name, msg.content),
+                    None => " Original message is not available".to_string(),
+                };
+                ui.add(Label::new(RichText::new(text).small().weak()).truncate());
+            });
+        }
         ui.horizontal(|ui| {
-            ui.add_sized(Vec2::splat(32.0), Image::new(&self.author.avatar_url));
+            ui.add_sized(
+                Vec2::splat(AVATAR_SIZE),
+                Image::new(&self.author.avatar_url),
+            );
             ui.vertical(|ui| {
-                ui.horizontal(|ui| {
-                    ui.heading(&self.author.name);
-                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
-                        ui.spacing();
-                        ui.button("");
-                        ui.weak("23:22");
-                    });
-                });
-                ui.label(&self.msg.content);
+                let menu = ui
+                    .horizontal(|ui| {
+                        ui.heading(&self.author.name);
+                        ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                            ui.spacing();
+                            let menu = ui
+                                .add_enabled_ui(self.actions.is_some(), |ui| {
+                                    ui.menu_button("", |ui| self.menu(ui)).inner
+                                })
+                                .inner;
+                            ui.weak(time::message_time(self.msg.created_at))
+                                .on_hover_text(time::full_time(self.msg.created_at));
+                            menu.flatten()
+                        })
+                        .inner
+                    })
+                    .inner;
+                self.content(ui).or(menu)
+            })
+            .inner
+        })
+        .inner
+    }
+
+    /// The actions of the menu button and the context menu.
+    fn menu(&self, ui: &mut egui::Ui) -> Option<MessageResponse> {
+        let (_, permissions) = self.actions?;
+        let mut ret = None;
+        if ui.button(" Reply").clicked() {
+            ret = Some(MessageResponse::Reply);
+        }
+        if permissions.edit && ui.button(" Edit").clicked() {
+            ret = Some(MessageResponse::Edit);
+        }
+        if permissions.pin {
+            let label = match self.msg.pinned {
+                true => " Unpin",
+                false => " Pin",
+            };
+            if ui.button(label).clicked() {
+                ret = Some(MessageResponse::Pin(!self.msg.pinned));
+            }
+        }
+        ui.menu_button(" Add reaction", |ui| {
+            ui.horizontal(|ui| {
+                for emoji in QUICK_REACTIONS {
+                    if ui.button(emoji).clicked() {
+                        ret = Some(MessageResponse::React(emoji.to_string()));
+                    }
+                }
             });
+        });
+        ui.separator();
+        if ui.button(" Copy text").clicked() {
+            ret = Some(MessageResponse::CopyText);
+        }
+        if ui.button(" Copy message link").clicked() {
+            ret = Some(MessageResponse::CopyLink);
+        }
+        if permissions.delete {
+            ui.separator();
+            let delete = RichText::new(" Delete").color(ui.visuals().error_fg_color);
+            if ui.button(delete).clicked() {
+                ret = Some(MessageResponse::Delete);
+            }
+        }
+        if ret.is_some() {
+            ui.close();
+        }
+        ret
+    }
+
+    fn content(&self, ui: &mut egui::Ui) -> Option<MessageResponse> {
+        match self.state {
+            MessageState::Sent => {
+                let mut ret = None;
+                if self.msg.edited_at.is_none() && !self.msg.pinned {
+                    ui.label(&self.msg.content);
+                } else {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(&self.msg.content);
+                        if let Some(edited_at) = self.msg.edited_at {
+                            let sense = match self.revisions {
+                                true => Sense::click(),
+                                false => Sense::hover(),
+                            };
+                            let marker = ui
+                                .add(
+                                    Label::new(RichText::new("(edited)").small().weak())
+                                        .sense(sense),
+                                )
+                                .on_hover_text(time::full_time(edited_at));
+                            if marker.clicked() {
+                                ret = Some(MessageResponse::ShowRevisions);
+                            }
+                        }
+                        if self.msg.pinned {
+                            ui.label(RichText::new("").small().weak())
+                                .on_hover_text("Pinned");
+                        }
+                    });
+                }
+                if !self.msg.reactions.is_empty() {
+                    ret = ret.or(self.reactions(ui));
+                }
+                ret
+            }
+            MessageState::Pending => {
+                ui.weak(&self.msg.content);
+                None
+            }
+            MessageState::Failed => {
+                ui.colored_label(ui.visuals().error_fg_color, &self.msg.content);
+                ui.small("Failed to send");
+                None
+            }
+        }
+    }
+
+    /// A button per emoji with the number of reactions, highlighted if the user reacted.
+    fn reactions(&self, ui: &mut egui::Ui) -> Option<MessageResponse> {
+        let me = self.actions.map(|(me, _)| me);
+        ui.horizontal_wrapped(|ui| {
+            let mut ret = None;
+            for reaction in &self.msg.reactions {
+                let mine = me.is_some_and(|me| reaction.user_ids.contains(&me));
+                let text = format!("{} {}", reaction.emoji, reaction.user_ids.len());
+                let button = ui.add_enabled(
+                    me.is_some(),
+                    Button::new(RichText::new(text).small()).selected(mine),
+                );
+                if button.clicked() && !mine {
+                    ret = Some(MessageResponse::React(reaction.emoji.clone()));
+                }
+            }
+            ret
         })
-        .response
+        .inner
+    }
+}
+
+/// A line across the message list with the day of the messages below it.
+pub struct DaySeparator(NaiveDate);
+
+impl DaySeparator {
+    pub fn new(date: NaiveDate) -> Self {
+        Self(date)
+    }
+}
+
+impl Widget for DaySeparator {
+    fn ui(self, ui: &mut egui::Ui) -> egui::Response {
+        let galley = WidgetText::from(RichText::new(time::day_label(self.0)).small()).into_galley(
+            ui,
+            Some(TextWrapMode::Extend),
+            f32::INFINITY,
+            TextStyle::Small,
+        );
+        let height = galley.size().y + ui.spacing().item_spacing.y * 2.0;
+        let (rect, response) =
+            ui.allocate_exact_size(Vec2::new(ui.available_width(), height), Sense::hover());
+        if ui.is_rect_visible(rect) {
+            let label = Align2::CENTER_CENTER.anchor_size(rect.center(), galley.size());
+            let gap = ui.spacing().item_spacing.x;
+            let stroke = ui.visuals().widgets.noninteractive.bg_stroke;
+            let painter = ui.painter();
+            painter.hline(rect.left()..=label.left() - gap, rect.center().y, stroke);
+            painter.hline(label.right() + gap..=rect.right(), rect.center().y, stroke);
+            painter.galley(label.min, galley, ui.visuals().weak_text_color());
+        }
+        response
     }
 }
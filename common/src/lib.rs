@@ -1,46 +1,503 @@
+mod model;
+pub mod snowflake;
+
+pub use model::*;
+pub use snowflake::Snowflake;
+
+use std::fmt::Display;
+
 use bincode::{
     Decode, Encode,
     error::{DecodeError, EncodeError},
 };
 
+/// Most memory a decoded client message may claim. Length prefixes are checked against it
+/// before anything is allocated, so a tiny frame can't make the server reserve gigabytes.
+pub const MAX_CLIENT_MESSAGE_SIZE: usize = 64 * 1024;
+/// Same for server messages, which carry whole guilds and pages of history.
+pub const MAX_SERVER_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+macro_rules! wire_format {
+    ($ty:ty, $limit:expr) => {
+        impl $ty {
+            pub fn encode(self) -> Result<Vec<u8>, EncodeError> {
+                bincode::encode_to_vec(self, bincode::config::standard())
+            }
+
+            pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+                let config = bincode::config::standard().with_limit::<{ $limit }>();
+                Ok(bincode::decode_from_slice(data, config)?.0)
+            }
+        }
+    };
+}
+
+/// Bumped whenever the wire format of [`ClientMessage`] or [`ServerMessage`] changes.
+pub const PROTOCOL_VERSION: u32 = 16;
+
+/// First message of every session. `Hello` must stay the first variant of
+/// [`ClientMessage`] so that any build can still decode it.
 #[derive(Encode, Decode, Debug)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub client_name: String,
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
 pub struct CreateGuild {
-    name: String,
+    pub name: String,
+    pub icon_url: String,
+}
+
+/// Chosen by the client for every [`SendMessage`] and echoed back in the resulting
+/// [`MessageCreate`], so the author can match it with the message it displayed optimistically.
+pub type Nonce = u64;
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct SendMessage {
+    pub channel_id: Snowflake,
+    pub content: String,
+    pub nonce: Nonce,
+    /// A message in the same channel to reply to.
+    pub reply_to: Option<Snowflake>,
+}
+
+/// Which page of a channel's history [`FetchMessages`] asks for, relative to a message id.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageCursor {
+    Latest,
+    Before(Snowflake),
+    After(Snowflake),
+    Around(Snowflake),
+}
+
+/// Asks for at most `limit` messages of a channel. The server clamps `limit` to
+/// [`MAX_FETCH_MESSAGES`] and replies with [`Messages`].
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct FetchMessages {
+    pub channel_id: Snowflake,
+    pub cursor: MessageCursor,
+    pub limit: u32,
+}
+
+pub const MAX_FETCH_MESSAGES: u32 = 100;
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct CreateInvite {
+    pub guild_id: Snowflake,
+    pub max_age_secs: Option<u32>,
+    pub max_uses: Option<u32>,
+}
+
+/// Replied to with [`InvitePreview`].
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct PreviewInvite {
+    pub code: String,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct JoinGuildByCode {
+    pub code: String,
+}
+
+/// Only the invite's creator and the guild's owner may revoke it.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct RevokeInvite {
+    pub code: String,
+}
+
+/// Path segment preceding the code in invite links, e.g. `http://host/invite/<code>`.
+pub const INVITE_PATH: &str = "invite/";
+
+/// Extracts the invite code from either a bare code or an invite link.
+pub fn parse_invite(input: &str) -> Option<&str> {
+    let input = input.trim().trim_end_matches('/');
+    let code = match input.rfind(INVITE_PATH) {
+        Some(i) => &input[i + INVITE_PATH.len()..],
+        None => input,
+    };
+    let valid = !code.is_empty() && code.chars().all(|c| c.is_ascii_alphanumeric());
+    valid.then_some(code)
+}
+
+/// Path segment preceding `<guild id>/<channel id>/<message id>` in message links.
+pub const MESSAGE_PATH: &str = "channels/";
+
+/// The message a message link points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageLink {
+    pub guild_id: Snowflake,
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+}
+
+/// Extracts the ids from a message link, e.g. `http://host/channels/<guild>/<channel>/<message>`.
+pub fn parse_message_link(input: &str) -> Option<MessageLink> {
+    let input = input.trim().trim_end_matches('/');
+    let i = input.rfind(MESSAGE_PATH)?;
+    let mut ids = input[i + MESSAGE_PATH.len()..].split('/');
+    let mut next = || ids.next()?.parse::<Snowflake>().ok();
+    let link = MessageLink {
+        guild_id: next()?,
+        channel_id: next()?,
+        message_id: next()?,
+    };
+    ids.next().is_none().then_some(link)
+}
+
+/// Chosen by the client for every [`Request`] and echoed back in the matching [`Ack`] or [`Error`].
+pub type RequestId = u32;
+
+/// Client messages that expect an [`Ack`] or an [`Error`] in reply.
+#[derive(Encode, Decode, Debug, Clone)]
+pub enum Request {
+    CreateGuild(CreateGuild),
+    SendMessage(SendMessage),
+    FetchMessages(FetchMessages),
+    CreateInvite(CreateInvite),
+    PreviewInvite(PreviewInvite),
+    JoinGuild(JoinGuildByCode),
+    RevokeInvite(RevokeInvite),
+    MarkRead(MarkRead),
+    EditMessage(EditMessage),
+    DeleteMessage(DeleteMessage),
+    FetchMessageRevisions(FetchMessageRevisions),
+    PinMessage(PinMessage),
+    AddReaction(AddReaction),
+}
+
+impl Request {
+    /// Name of the variant, for logs and metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Request::CreateGuild(_) => "CreateGuild",
+            Request::SendMessage(_) => "SendMessage",
+            Request::FetchMessages(_) => "FetchMessages",
+            Request::CreateInvite(_) => "CreateInvite",
+            Request::PreviewInvite(_) => "PreviewInvite",
+            Request::JoinGuild(_) => "JoinGuild",
+            Request::RevokeInvite(_) => "RevokeInvite",
+            Request::MarkRead(_) => "MarkRead",
+            Request::EditMessage(_) => "EditMessage",
+            Request::DeleteMessage(_) => "DeleteMessage",
+            Request::FetchMessageRevisions(_) => "FetchMessageRevisions",
+            Request::PinMessage(_) => "PinMessage",
+            Request::AddReaction(_) => "AddReaction",
+        }
+    }
+}
+
+/// Marks everything up to `message_id` in a text channel as read.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct MarkRead {
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+}
+
+/// Replaces the content of a message. Only its author may edit it.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct EditMessage {
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+    pub content: String,
+}
+
+/// Removes a message. Its author and the guild's moderators may delete it.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct DeleteMessage {
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+}
+
+/// Asks for the versions a message had before its edits. Replied to with [`MessageRevisions`],
+/// for moderators only.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct FetchMessageRevisions {
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+}
+
+/// Pins or unpins a message. Only the guild's moderators may.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct PinMessage {
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+    pub pinned: bool,
+}
+
+/// Reacts to a message with an emoji. Reacting twice with the same emoji does nothing.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct AddReaction {
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+    pub emoji: String,
+}
+
+/// Identifies a session on the server, handed out in [`Welcome`].
+pub type SessionId = u64;
+
+/// Sent instead of [`Hello`] after a reconnect to pick up the session where it left off.
+/// `last_seq` is the `seq` of the last [`ServerMessage::Sequenced`] the client received.
+#[derive(Encode, Decode, Debug)]
+pub struct Resume {
+    pub session_id: SessionId,
+    pub last_seq: u64,
 }
 
 #[derive(Encode, Decode, Debug)]
 pub enum ClientMessage {
-    CreateGuild(CreateGuild),
+    Hello(Hello),
+    Request {
+        request_id: RequestId,
+        request: Request,
+    },
+    Resume(Resume),
+    /// Answer to [`ServerMessage::Heartbeat`]. The server echoes `nonce` back in
+    /// [`ServerMessage::Pong`], which lets the client measure the round-trip time.
+    Ping {
+        nonce: u64,
+    },
 }
 
+wire_format!(ClientMessage, MAX_CLIENT_MESSAGE_SIZE);
+
 impl ClientMessage {
-    pub fn encode(self) -> Result<Vec<u8>, EncodeError> {
-        bincode::encode_to_vec(self, bincode::config::standard())
+    /// Name of the variant, or of the request it carries, for logs and metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientMessage::Hello(_) => "Hello",
+            ClientMessage::Request { request, .. } => request.name(),
+            ClientMessage::Resume(_) => "Resume",
+            ClientMessage::Ping { .. } => "Ping",
+        }
     }
+}
 
-    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
-        Ok(bincode::decode_from_slice(data, bincode::config::standard())?.0)
-    }
+/// Reply to an accepted [`Hello`].
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Welcome {
+    pub protocol_version: u32,
+    pub server_name: String,
+    pub capabilities: Vec<String>,
+    pub session_id: SessionId,
+    /// How often the server sends [`ServerMessage::Heartbeat`].
+    pub heartbeat_interval_ms: u32,
 }
 
-#[derive(Encode, Decode, Debug)]
+/// Reply to an accepted [`Resume`]. The missed messages follow, then the session carries on.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Resumed {
+    pub session_id: SessionId,
+}
+
+/// Reply to a rejected [`Hello`], sent right before the server closes the socket.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Refused {
+    pub protocol_version: u32,
+    pub reason: String,
+}
+
+/// Everything a new session starts from, sent right after [`Welcome`]. Later events are
+/// deltas on top of it.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Ready {
+    pub user: User,
+    pub guilds: Vec<Guild>,
+    pub read_states: Vec<ReadState>,
+}
+
+/// The client became a member of `guild`, either by creating it or through an invite.
+#[derive(Encode, Decode, Debug, Clone)]
 pub struct JoinGuild {
-    id: u32,
-    name: String,
-    icon_url: String,
+    pub guild: Guild,
 }
 
-#[derive(Encode, Decode, Debug)]
+/// Someone else joined a guild the client is a member of.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct MemberJoin {
+    pub guild_id: Snowflake,
+    pub user_id: Snowflake,
+    pub member: GuildMember,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Invite {
+    pub code: String,
+    pub guild_id: Snowflake,
+    pub creator_id: Snowflake,
+    /// Unix timestamp in seconds, `None` if the invite never expires.
+    pub expires_at: Option<i64>,
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
+
+/// What a user gets to see about a guild before accepting its invite.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct InvitePreview {
+    pub code: String,
+    pub guild_name: String,
+    pub icon_url: String,
+    pub member_count: u32,
+}
+
+/// The request completed successfully. Any resulting state changes are sent before the ack.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Ack {
+    pub request_id: RequestId,
+}
+
+/// Stable error codes. New codes must only ever be appended.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Internal,
+    InvalidRequest,
+    NotFound,
+    NotPermitted,
+    NameTaken,
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ErrorCode::Internal => "internal server error",
+            ErrorCode::InvalidRequest => "invalid request",
+            ErrorCode::NotFound => "not found",
+            ErrorCode::NotPermitted => "not permitted",
+            ErrorCode::NameTaken => "name taken",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The request failed. `request_id` is `None` when the failing frame could not be decoded.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Error {
+    pub request_id: Option<RequestId>,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// A message was posted in one of the guilds the client is a member of.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct MessageCreate {
+    pub guild_id: Snowflake,
+    pub channel_id: Snowflake,
+    pub message: Message,
+    pub nonce: Option<Nonce>,
+}
+
+/// Reply to [`FetchMessages`], ordered from oldest to newest.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Messages {
+    pub guild_id: Snowflake,
+    pub channel_id: Snowflake,
+    pub cursor: MessageCursor,
+    pub messages: Vec<Message>,
+}
+
+/// A message in one of the client's guilds was edited, pinned or unpinned.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct MessageUpdate {
+    pub guild_id: Snowflake,
+    pub channel_id: Snowflake,
+    pub message: Message,
+}
+
+/// A message in one of the client's guilds was deleted.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct MessageDelete {
+    pub guild_id: Snowflake,
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+}
+
+/// Someone reacted to a message in one of the client's guilds.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct ReactionAdd {
+    pub guild_id: Snowflake,
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+    pub user_id: Snowflake,
+    pub emoji: String,
+}
+
+/// Reply to [`FetchMessageRevisions`], ordered from oldest to newest. The current content is
+/// not included.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct MessageRevisions {
+    pub guild_id: Snowflake,
+    pub channel_id: Snowflake,
+    pub message_id: Snowflake,
+    pub revisions: Vec<MessageRevision>,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
 pub enum ServerMessage {
+    Welcome(Welcome),
+    Refused(Refused),
+    Ack(Ack),
+    Error(Error),
+    Ready(Ready),
     JoinGuild(JoinGuild),
+    MessageCreate(MessageCreate),
+    Messages(Messages),
+    MemberJoin(MemberJoin),
+    /// Reply to [`CreateInvite`].
+    InviteCreated(Invite),
+    /// Reply to [`PreviewInvite`].
+    InvitePreview(InvitePreview),
+    /// Every message after the handshake is numbered within its session, starting at 1.
+    Sequenced {
+        seq: u64,
+        message: Box<ServerMessage>,
+    },
+    Resumed(Resumed),
+    /// Reply to a [`Resume`] the server can't honour. The client should send [`Hello`] and
+    /// treat everything it knows as stale.
+    InvalidSession,
+    /// One of the user's sessions marked a channel as read.
+    ReadState(ReadState),
+    /// Sent every heartbeat interval, outside of the sequence. Sessions that don't answer a few
+    /// of them in a row with [`ClientMessage::Ping`] are considered dead and closed.
+    Heartbeat,
+    Pong {
+        nonce: u64,
+    },
+    /// The server is going away, e.g. for a deploy. Everything queued for the session was sent
+    /// before this, so the client should simply reconnect rather than report an error.
+    Reconnect {
+        reason: String,
+    },
+    /// The request was not handled because the session or user sent too many of its kind. It
+    /// stays pending, the client should send it again after `retry_after_ms`.
+    RateLimited {
+        request_id: RequestId,
+        retry_after_ms: u32,
+    },
+    MessageUpdate(MessageUpdate),
+    MessageDelete(MessageDelete),
+    MessageRevisions(MessageRevisions),
+    ReactionAdd(ReactionAdd),
 }
 
-impl ServerMessage {
-    pub fn encode(self) -> Result<Vec<u8>, EncodeError> {
-        bincode::encode_to_vec(self, bincode::config::standard())
-    }
+wire_format!(ServerMessage, MAX_SERVER_MESSAGE_SIZE);
 
-    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
-        Ok(bincode::decode_from_slice(data, bincode::config::standard())?.0)
-    }
+/// Body of the `POST /register` and `POST /login` requests.
+#[derive(Encode, Decode, Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+wire_format!(Credentials, MAX_CLIENT_MESSAGE_SIZE);
+
+/// Reply to `POST /register` and `POST /login`. The token authenticates the `/ws`
+/// upgrade through an `Authorization: Bearer <token>` header.
+#[derive(Encode, Decode, Debug)]
+pub enum AuthResponse {
+    Ok { token: String, user: User },
+    Error { code: ErrorCode, message: String },
 }
+
+wire_format!(AuthResponse, MAX_SERVER_MESSAGE_SIZE);
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use bincode::{Decode, Encode};
+
+use crate::Snowflake;
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct User {
+    pub id: Snowflake,
+    pub name: String,
+    pub avatar_url: String,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Message {
+    pub id: Snowflake,
+    pub author_id: Snowflake,
+    pub content: String,
+    /// Unix timestamp in milliseconds, as recorded by the server.
+    pub created_at: i64,
+    /// Unix timestamp in milliseconds of the last edit, `None` if the message was never edited.
+    pub edited_at: Option<i64>,
+    /// The message in the same channel this one replies to. It may since have been deleted.
+    pub reply_to: Option<Snowflake>,
+    pub pinned: bool,
+    /// Grouped by emoji, in the order each emoji was first used.
+    pub reactions: Vec<Reaction>,
+}
+
+impl Message {
+    /// Records that `user_id` reacted with `emoji`. Returns whether they had not already.
+    pub fn add_reaction(&mut self, emoji: &str, user_id: Snowflake) -> bool {
+        match self.reactions.iter_mut().find(|r| r.emoji == emoji) {
+            Some(reaction) if reaction.user_ids.contains(&user_id) => false,
+            Some(reaction) => {
+                reaction.user_ids.push(user_id);
+                true
+            }
+            None => {
+                self.reactions.push(Reaction {
+                    emoji: emoji.to_string(),
+                    user_ids: vec![user_id],
+                });
+                true
+            }
+        }
+    }
+}
+
+/// Everyone who reacted to a message with the same emoji.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Reaction {
+    pub emoji: String,
+    pub user_ids: Vec<Snowflake>,
+}
+
+/// An earlier version of a message's content, replaced by an edit.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct MessageRevision {
+    pub content: String,
+    /// Unix timestamp in milliseconds at which this version was posted or edited in.
+    pub written_at: i64,
+}
+
+#[derive(Encode, Decode, Debug, Clone, Default)]
+pub struct TextChannel {
+    pub messages: Vec<Message>,
+    pub last_message_id: Option<Snowflake>,
+}
+
+/// The newest message a user has read in a text channel.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct ReadState {
+    pub channel_id: Snowflake,
+    pub last_read_id: Snowflake,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub enum ChannelKind {
+    Text(TextChannel),
+    Voice,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Channel {
+    pub id: Snowflake,
+    pub name: String,
+    pub kind: ChannelKind,
+    pub description: String,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct GuildMember {
+    pub name: String,
+    pub avatar_url: String,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Guild {
+    pub id: Snowflake,
+    pub name: String,
+    pub icon_url: String,
+    pub owner_id: Option<Snowflake>,
+    pub channels: Vec<Channel>,
+    pub members: HashMap<Snowflake, GuildMember>,
+}
+
+impl Guild {
+    /// Moderators may delete and pin anyone's messages and read their edit history. For now
+    /// that is only the owner.
+    pub fn is_moderator(&self, user_id: Snowflake) -> bool {
+        self.owner_id == Some(user_id)
+    }
+}
@@ -0,0 +1,56 @@
+//! Ids of users, guilds, channels and messages. They are generated by the server from the time
+//! they were created, the worker that created them and a per-millisecond sequence:
+//!
+//! ```text
+//!  63                                   22        12          0
+//! | milliseconds since EPOCH_MS (42 bits) | worker | sequence |
+//! ```
+//!
+//! So ids sort by creation time, and a point in time can stand in for an id wherever ids are
+//! compared, e.g. in a [`crate::MessageCursor`].
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub type Snowflake = u64;
+
+/// 2025-01-01T00:00:00Z, in milliseconds since the Unix epoch.
+pub const EPOCH_MS: u64 = 1_735_689_600_000;
+
+pub const WORKER_BITS: u32 = 10;
+pub const SEQUENCE_BITS: u32 = 12;
+
+pub const MAX_WORKER_ID: u16 = (1 << WORKER_BITS) - 1;
+pub const MAX_SEQUENCE: u16 = (1 << SEQUENCE_BITS) - 1;
+
+const TIMESTAMP_SHIFT: u32 = WORKER_BITS + SEQUENCE_BITS;
+
+/// Puts an id together. `unix_ms` must not be before [`EPOCH_MS`]; `worker` and `sequence` are
+/// truncated to their widths.
+pub const fn compose(unix_ms: u64, worker: u16, sequence: u16) -> Snowflake {
+    ((unix_ms - EPOCH_MS) << TIMESTAMP_SHIFT)
+        | (((worker & MAX_WORKER_ID) as u64) << SEQUENCE_BITS)
+        | (sequence & MAX_SEQUENCE) as u64
+}
+
+/// When the id was generated, in milliseconds since the Unix epoch.
+pub const fn timestamp_ms(id: Snowflake) -> u64 {
+    (id >> TIMESTAMP_SHIFT) + EPOCH_MS
+}
+
+/// When the id was generated.
+pub fn created_at(id: Snowflake) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(timestamp_ms(id))
+}
+
+pub const fn worker(id: Snowflake) -> u16 {
+    (id >> SEQUENCE_BITS) as u16 & MAX_WORKER_ID
+}
+
+/// The smallest id that could have been generated at `time`, for cursors such as
+/// `MessageCursor::After(snowflake::at(time))`. Times before [`EPOCH_MS`] map to 0.
+pub fn at(time: SystemTime) -> Snowflake {
+    let unix_ms = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+    compose(unix_ms.max(EPOCH_MS), 0, 0)
+}
@@ -0,0 +1,12 @@
+CREATE TABLE invites (
+    code TEXT PRIMARY KEY,
+    guild_id INTEGER NOT NULL REFERENCES guilds (id) ON DELETE CASCADE,
+    creator_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ,
+    max_uses INTEGER,
+    uses INTEGER NOT NULL DEFAULT 0,
+    revoked BOOLEAN NOT NULL DEFAULT false
+);
+
+CREATE INDEX invites_guild_id_idx ON invites (guild_id);
@@ -0,0 +1,6 @@
+CREATE TABLE read_states (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    channel_id INTEGER NOT NULL REFERENCES channels (id) ON DELETE CASCADE,
+    last_read_id INTEGER NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, channel_id)
+);
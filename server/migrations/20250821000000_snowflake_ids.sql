@@ -0,0 +1,19 @@
+-- Ids are now snowflakes generated by the server. Rows created before keep their small serial
+-- ids, which still sort before every new one.
+ALTER TABLE users ALTER COLUMN id DROP DEFAULT, ALTER COLUMN id TYPE BIGINT;
+ALTER TABLE guilds ALTER COLUMN id DROP DEFAULT, ALTER COLUMN id TYPE BIGINT;
+ALTER TABLE channels ALTER COLUMN id DROP DEFAULT, ALTER COLUMN id TYPE BIGINT;
+ALTER TABLE messages ALTER COLUMN id DROP DEFAULT, ALTER COLUMN id TYPE BIGINT;
+
+DROP SEQUENCE users_id_seq, guilds_id_seq, channels_id_seq, messages_id_seq;
+
+ALTER TABLE guilds ALTER COLUMN owner_id TYPE BIGINT;
+ALTER TABLE channels ALTER COLUMN guild_id TYPE BIGINT;
+ALTER TABLE guild_members ALTER COLUMN guild_id TYPE BIGINT, ALTER COLUMN user_id TYPE BIGINT;
+ALTER TABLE messages ALTER COLUMN channel_id TYPE BIGINT, ALTER COLUMN author_id TYPE BIGINT;
+ALTER TABLE sessions ALTER COLUMN user_id TYPE BIGINT;
+ALTER TABLE invites ALTER COLUMN guild_id TYPE BIGINT, ALTER COLUMN creator_id TYPE BIGINT;
+ALTER TABLE read_states
+    ALTER COLUMN user_id TYPE BIGINT,
+    ALTER COLUMN channel_id TYPE BIGINT,
+    ALTER COLUMN last_read_id TYPE BIGINT;
@@ -0,0 +1,10 @@
+CREATE TABLE message_revisions (
+    message_id BIGINT NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    written_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX message_revisions_message_id_idx ON message_revisions (message_id, written_at);
+
+-- Deleting the message a user read up to must not forget how far they read.
+ALTER TABLE read_states DROP CONSTRAINT read_states_last_read_id_fkey;
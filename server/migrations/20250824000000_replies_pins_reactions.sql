@@ -0,0 +1,11 @@
+-- Not a foreign key: a reply outlives the message it replied to.
+ALTER TABLE messages ADD COLUMN reply_to BIGINT;
+ALTER TABLE messages ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE message_reactions (
+    message_id BIGINT NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    emoji TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (message_id, emoji, user_id)
+);
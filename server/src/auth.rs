@@ -0,0 +1,190 @@
+use std::sync::{Arc, LazyLock};
+
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use common::{AuthResponse, Credentials, ErrorCode, User};
+use sha2::{Digest, Sha256};
+
+use crate::{AppState, error::RequestError, storage::StorageError};
+
+const SESSION_TTL_DAYS: i32 = 30;
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_USERNAME_LEN: usize = 32;
+
+/// Checked against when the username is unknown, so rejecting it takes as long as rejecting a
+/// wrong password and response times don't tell which usernames exist.
+static DUMMY_PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(b"not anyone's password", &salt)
+        .expect("hashing with the default parameters cannot fail")
+        .to_string()
+});
+
+pub async fn register(State(state): State<Arc<AppState>>, body: Bytes) -> Response {
+    reply(try_register(&state, &body).await)
+}
+
+pub async fn login(State(state): State<Arc<AppState>>, body: Bytes) -> Response {
+    reply(try_login(&state, &body).await)
+}
+
+/// Resolves the `Authorization: Bearer <token>` header of a `/ws` upgrade to a user.
+pub async fn authenticate(state: &AppState, headers: &HeaderMap) -> Result<User, StatusCode> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    match state.storage.find_session_user(&token_hash(token)).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(StatusCode::UNAUTHORIZED),
+        Err(err) => {
+            tracing::error!("Failed to look up session: {}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn try_register(state: &AppState, body: &[u8]) -> Result<(String, User), RequestError> {
+    let Credentials { username, password } = decode_credentials(body)?;
+    let username = username.trim().to_string();
+
+    let valid_username = (2..=MAX_USERNAME_LEN).contains(&username.chars().count())
+        && username
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if !valid_username {
+        return Err(RequestError::new(
+            ErrorCode::InvalidRequest,
+            format!(
+                "usernames must be 2 to {} letters, digits, '_', '.' or '-'",
+                MAX_USERNAME_LEN
+            ),
+        ));
+    }
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(RequestError::new(
+            ErrorCode::InvalidRequest,
+            format!("passwords must be at least {} characters", MIN_PASSWORD_LEN),
+        ));
+    }
+
+    let password_hash = hash_password(password).await?;
+    let user = match state.storage.create_user(&username, &password_hash).await {
+        Ok(user) => user,
+        Err(StorageError::Conflict) => {
+            return Err(RequestError::new(
+                ErrorCode::NameTaken,
+                "that username is already taken",
+            ));
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let token = start_session(state, &user).await?;
+    Ok((token, user))
+}
+
+async fn try_login(state: &AppState, body: &[u8]) -> Result<(String, User), RequestError> {
+    let Credentials { username, password } = decode_credentials(body)?;
+    let invalid = || RequestError::new(ErrorCode::NotPermitted, "invalid username or password");
+
+    let login = state.storage.find_login(username.trim()).await?;
+    let password_hash = login
+        .as_ref()
+        .map(|(_, password_hash)| password_hash.clone());
+    let verified = verify_password(password, password_hash).await?;
+    let (user, _) = login.filter(|_| verified).ok_or_else(invalid)?;
+
+    let token = start_session(state, &user).await?;
+    Ok((token, user))
+}
+
+fn decode_credentials(body: &[u8]) -> Result<Credentials, RequestError> {
+    Credentials::decode(body)
+        .map_err(|_| RequestError::new(ErrorCode::InvalidRequest, "malformed credentials"))
+}
+
+async fn start_session(state: &AppState, user: &User) -> Result<String, RequestError> {
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    state
+        .storage
+        .create_session(&token_hash(&token), user.id, SESSION_TTL_DAYS)
+        .await?;
+    Ok(token)
+}
+
+/// Only a digest of each token is stored, so a leaked sessions table can't be replayed.
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+async fn hash_password(password: String) -> Result<String, RequestError> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .ok()
+    .and_then(Result::ok)
+    .ok_or_else(|| RequestError::new(ErrorCode::Internal, "failed to hash password"))
+}
+
+/// Without a `password_hash` the password is checked against [`DUMMY_PASSWORD_HASH`] and never
+/// matches.
+async fn verify_password(
+    password: String,
+    password_hash: Option<String>,
+) -> Result<bool, RequestError> {
+    tokio::task::spawn_blocking(move || {
+        let known = password_hash.is_some();
+        let password_hash = password_hash.as_deref().unwrap_or(&DUMMY_PASSWORD_HASH);
+        let hash = PasswordHash::new(password_hash).ok()?;
+        Some(
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+                && known,
+        )
+    })
+    .await
+    .ok()
+    .flatten()
+    .ok_or_else(|| RequestError::new(ErrorCode::Internal, "failed to verify password"))
+}
+
+fn reply(result: Result<(String, User), RequestError>) -> Response {
+    let (status, response) = match result {
+        Ok((token, user)) => (StatusCode::OK, AuthResponse::Ok { token, user }),
+        Err(RequestError { code, message }) => {
+            let status = match code {
+                ErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+                ErrorCode::NotPermitted => StatusCode::UNAUTHORIZED,
+                ErrorCode::NameTaken => StatusCode::CONFLICT,
+                ErrorCode::NotFound => StatusCode::NOT_FOUND,
+                ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (status, AuthResponse::Error { code, message })
+        }
+    };
+
+    match response.encode() {
+        Ok(body) => (status, body).into_response(),
+        Err(err) => {
+            tracing::error!("Failed to encode auth response: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    env,
+    ffi::OsString,
+    fmt::Display,
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use common::snowflake;
+use serde::Deserialize;
+
+use crate::logging::{Filter, LogFormat};
+
+/// Read when neither `--config` nor `CONFIG_FILE` names a file, if it exists.
+pub const DEFAULT_CONFIG_PATH: &str = "server.toml";
+
+pub const USAGE: &str = "\
+Usage: server [OPTIONS]
+
+Settings are layered: built-in defaults, then the config file, then environment variables
+(a .env file is loaded first if present), then these flags.
+
+Options:
+  --config <PATH>               config file [env: CONFIG_FILE] [default: server.toml]
+  --bind <ADDR>                 address to listen on [env: BIND_ADDR]
+  --database-url <URL>          Postgres url, or memory: [env: DATABASE_URL]
+  --max-connections <N>         database pool size [env: DATABASE_MAX_CONNECTIONS]
+  --worker-id <N>               0 to 1023, unique per server sharing a database [env: WORKER_ID]
+  --upload-dir <PATH>           where uploaded files are stored [env: UPLOAD_DIR]
+  --max-message-len <N>         [env: MAX_MESSAGE_LEN]
+  --max-guild-name-len <N>      [env: MAX_GUILD_NAME_LEN]
+  --max-frame-bytes <N>         [env: MAX_FRAME_BYTES]
+  --heartbeat-interval <SECS>   [env: HEARTBEAT_INTERVAL_SECS]
+  --heartbeat-max-missed <N>    [env: HEARTBEAT_MAX_MISSED]
+  --shutdown-timeout <SECS>     how long to wait for sessions to drain [env: SHUTDOWN_TIMEOUT_SECS]
+  --log-filter <FILTER>         e.g. info,server::hub=debug [env: LOG_FILTER]
+  --log-format <FORMAT>         pretty or json [env: LOG_FORMAT]
+  -h, --help                    print this help
+";
+
+/// Everything the server can be configured with. See `server.example.toml` for the file format.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: SocketAddr,
+    pub database: DatabaseConfig,
+    /// Part of every id this server generates. Servers sharing a database need distinct ones.
+    pub worker_id: u16,
+    pub upload_dir: PathBuf,
+    pub limits: Limits,
+    pub rate_limits: RateLimits,
+    pub heartbeat: HeartbeatConfig,
+    /// How long a graceful shutdown waits for sessions to finish.
+    pub shutdown_timeout_secs: u64,
+    pub log: LogConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// A Postgres connection string, or `memory:` for in-memory storage.
+    pub url: String,
+    pub max_connections: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub filter: Filter,
+    pub format: LogFormat,
+}
+
+/// Bounds on what clients may send.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// In characters.
+    pub max_message_len: usize,
+    /// In characters.
+    pub max_guild_name_len: usize,
+    /// Largest websocket frame or message a client may send, in bytes.
+    pub max_frame_bytes: usize,
+}
+
+/// Token buckets: up to `burst` at once, refilled by `per_minute`.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+/// Request limits of each session and of each user across all their sessions, plus a limit on
+/// all frames of a session, past which it is closed.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    pub frames: RateLimit,
+    pub session: RequestLimits,
+    pub user: RequestLimits,
+}
+
+/// A table of these has to list every category.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestLimits {
+    pub send_message: RateLimit,
+    pub create_guild: RateLimit,
+    /// Joining guilds and previewing invites.
+    pub join_guild: RateLimit,
+    /// Every other request.
+    pub other: RateLimit,
+}
+
+/// How often sessions get a [`common::ServerMessage::Heartbeat`] and how many of them may go
+/// unanswered before the session is considered dead.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    pub interval_secs: u64,
+    pub max_missed: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            database: DatabaseConfig::default(),
+            worker_id: 0,
+            upload_dir: PathBuf::from("uploads"),
+            limits: Limits::default(),
+            rate_limits: RateLimits::default(),
+            heartbeat: HeartbeatConfig::default(),
+            shutdown_timeout_secs: 10,
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: Filter::default(),
+            format: LogFormat::Pretty,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            max_connections: 5,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_message_len: 4000,
+            max_guild_name_len: 100,
+            max_frame_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl RateLimit {
+    const fn new(burst: u32, per_minute: u32) -> Self {
+        Self { burst, per_minute }
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            frames: RateLimit::new(60, 600),
+            session: RequestLimits {
+                send_message: RateLimit::new(10, 60),
+                create_guild: RateLimit::new(3, 5),
+                join_guild: RateLimit::new(5, 10),
+                other: RateLimit::new(30, 300),
+            },
+            user: RequestLimits {
+                send_message: RateLimit::new(15, 90),
+                create_guild: RateLimit::new(3, 5),
+                join_guild: RateLimit::new(5, 10),
+                other: RateLimit::new(60, 600),
+            },
+        }
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            max_missed: 3,
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        err: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        err: toml::de::Error,
+    },
+    Args(pico_args::Error),
+    /// A setting was present but unusable. `key` names where it came from, e.g. an environment
+    /// variable, a flag or a key in the config file.
+    Invalid {
+        key: String,
+        reason: String,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read { path, err } => write!(f, "cannot read {}: {}", path.display(), err),
+            ConfigError::Parse { path, err } => write!(f, "in {}: {}", path.display(), err),
+            ConfigError::Args(err) => err.fmt(f),
+            ConfigError::Invalid { key, reason } => write!(f, "{}: {}", key, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<pico_args::Error> for ConfigError {
+    fn from(err: pico_args::Error) -> Self {
+        ConfigError::Args(err)
+    }
+}
+
+impl Config {
+    /// Loads `.env`, then layers the config file, the environment and the command line of this
+    /// process over the defaults.
+    pub fn load() -> Result<Self, ConfigError> {
+        if let Err(err) = dotenvy::dotenv()
+            && !err.not_found()
+        {
+            return Err(ConfigError::Invalid {
+                key: ".env".to_string(),
+                reason: err.to_string(),
+            });
+        }
+        Self::from_sources(|name| env::var(name).ok(), env::args_os().skip(1).collect())
+    }
+
+    /// Layers the config file, the variables returned by `var` and the flags in `args` over the
+    /// defaults, then validates the result.
+    pub fn from_sources(
+        var: impl Fn(&str) -> Option<String>,
+        args: Vec<OsString>,
+    ) -> Result<Self, ConfigError> {
+        let mut args = pico_args::Arguments::from_vec(args);
+
+        let path = match args.opt_value_from_str::<_, PathBuf>("--config")? {
+            Some(path) => Some(path),
+            None => var("CONFIG_FILE").map(PathBuf::from),
+        };
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        config.apply(|name, _| Ok(var(name).map(|value| (name.to_string(), value))))?;
+        config.apply(|_, flag| {
+            let value = args.opt_value_from_str::<_, String>(flag)?;
+            Ok(value.map(|value| (flag.to_string(), value)))
+        })?;
+
+        let unexpected = args.finish();
+        if let Some(arg) = unexpected.first() {
+            return Err(ConfigError::Invalid {
+                key: arg.to_string_lossy().into_owned(),
+                reason: "unexpected argument, see --help".to_string(),
+            });
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|err| ConfigError::Read {
+            path: path.to_path_buf(),
+            err,
+        })?;
+        toml::from_str(&text).map_err(|err| ConfigError::Parse {
+            path: path.to_path_buf(),
+            err,
+        })
+    }
+
+    /// Overrides every setting that `lookup` has a value for. It is called with the name of the
+    /// environment variable and the flag of each setting and returns where the value came from
+    /// along with the value.
+    fn apply(
+        &mut self,
+        mut lookup: impl FnMut(
+            &'static str,
+            &'static str,
+        ) -> Result<Option<(String, String)>, ConfigError>,
+    ) -> Result<(), ConfigError> {
+        set(&mut self.bind, lookup("BIND_ADDR", "--bind")?)?;
+        set(
+            &mut self.database.url,
+            lookup("DATABASE_URL", "--database-url")?,
+        )?;
+        set(
+            &mut self.database.max_connections,
+            lookup("DATABASE_MAX_CONNECTIONS", "--max-connections")?,
+        )?;
+        set(&mut self.worker_id, lookup("WORKER_ID", "--worker-id")?)?;
+        set(&mut self.upload_dir, lookup("UPLOAD_DIR", "--upload-dir")?)?;
+        set(
+            &mut self.limits.max_message_len,
+            lookup("MAX_MESSAGE_LEN", "--max-message-len")?,
+        )?;
+        set(
+            &mut self.limits.max_guild_name_len,
+            lookup("MAX_GUILD_NAME_LEN", "--max-guild-name-len")?,
+        )?;
+        set(
+            &mut self.limits.max_frame_bytes,
+            lookup("MAX_FRAME_BYTES", "--max-frame-bytes")?,
+        )?;
+        set(
+            &mut self.heartbeat.interval_secs,
+            lookup("HEARTBEAT_INTERVAL_SECS", "--heartbeat-interval")?,
+        )?;
+        set(
+            &mut self.heartbeat.max_missed,
+            lookup("HEARTBEAT_MAX_MISSED", "--heartbeat-max-missed")?,
+        )?;
+        set(
+            &mut self.shutdown_timeout_secs,
+            lookup("SHUTDOWN_TIMEOUT_SECS", "--shutdown-timeout")?,
+        )?;
+        set(&mut self.log.filter, lookup("LOG_FILTER", "--log-filter")?)?;
+        set(&mut self.log.format, lookup("LOG_FORMAT", "--log-format")?)?;
+        Ok(())
+    }
+
+    /// Rejects settings that parse but would leave the server unusable.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, reason: &str| {
+            Err(ConfigError::Invalid {
+                key: key.to_string(),
+                reason: reason.to_string(),
+            })
+        };
+        if self.database.url.is_empty() {
+            return invalid(
+                "database.url",
+                "missing, set DATABASE_URL or use memory: for in-memory storage",
+            );
+        }
+        if self.database.max_connections == 0 {
+            return invalid("database.max_connections", "must be at least 1");
+        }
+        if self.worker_id > snowflake::MAX_WORKER_ID {
+            return invalid("worker_id", "must be at most 1023");
+        }
+        if self.upload_dir.as_os_str().is_empty() {
+            return invalid("upload_dir", "must not be empty");
+        }
+        if self.limits.max_message_len == 0 {
+            return invalid("limits.max_message_len", "must be at least 1");
+        }
+        if self.limits.max_guild_name_len == 0 {
+            return invalid("limits.max_guild_name_len", "must be at least 1");
+        }
+        if self.limits.max_frame_bytes < 1024 {
+            return invalid("limits.max_frame_bytes", "must be at least 1024");
+        }
+        let rate_limits = [
+            ("rate_limits.frames", self.rate_limits.frames),
+            (
+                "rate_limits.session.send_message",
+                self.rate_limits.session.send_message,
+            ),
+            (
+                "rate_limits.session.create_guild",
+                self.rate_limits.session.create_guild,
+            ),
+            (
+                "rate_limits.session.join_guild",
+                self.rate_limits.session.join_guild,
+            ),
+            ("rate_limits.session.other", self.rate_limits.session.other),
+            (
+                "rate_limits.user.send_message",
+                self.rate_limits.user.send_message,
+            ),
+            (
+                "rate_limits.user.create_guild",
+                self.rate_limits.user.create_guild,
+            ),
+            (
+                "rate_limits.user.join_guild",
+                self.rate_limits.user.join_guild,
+            ),
+            ("rate_limits.user.other", self.rate_limits.user.other),
+        ];
+        for (key, limit) in rate_limits {
+            if limit.burst == 0 || limit.per_minute == 0 {
+                return invalid(key, "burst and per_minute must be at least 1");
+            }
+        }
+        if self.heartbeat.interval_secs == 0 {
+            return invalid("heartbeat.interval_secs", "must be at least 1");
+        }
+        // Clients are told the interval in milliseconds, as a u32.
+        if self.heartbeat.interval_secs > u64::from(u32::MAX) / 1000 {
+            return invalid("heartbeat.interval_secs", "must be at most 4294967");
+        }
+        if self.heartbeat.max_missed == 0 {
+            return invalid("heartbeat.max_missed", "must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Parses `value` into `field`, naming the source of the value if it does not parse.
+fn set<T>(field: &mut T, value: Option<(String, String)>) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some((key, value)) = value {
+        *field = value.parse().map_err(|err: T::Err| ConfigError::Invalid {
+            key,
+            reason: format!("{:?} is invalid: {}", value, err),
+        })?;
+    }
+    Ok(())
+}
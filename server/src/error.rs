@@ -0,0 +1,34 @@
+use common::{ErrorCode, RequestId, ServerMessage};
+
+use crate::storage::StorageError;
+
+/// Failure of a single [`common::Request`], reported back to the client as [`common::Error`].
+#[derive(Debug)]
+pub struct RequestError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl RequestError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn into_message(self, request_id: Option<RequestId>) -> ServerMessage {
+        ServerMessage::Error(common::Error {
+            request_id,
+            code: self.code,
+            message: self.message,
+        })
+    }
+}
+
+impl From<StorageError> for RequestError {
+    fn from(err: StorageError) -> Self {
+        tracing::error!("Storage error: {}", err);
+        Self::new(ErrorCode::Internal, "internal server error")
+    }
+}
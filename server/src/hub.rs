@@ -0,0 +1,261 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use common::{ServerMessage, SessionId, Snowflake};
+use tokio::sync::mpsc;
+
+/// Outbound messages a session may have queued before it is considered too slow and dropped.
+const SESSION_QUEUE_LEN: usize = 256;
+/// Sent messages kept per session so that a reconnecting client can catch up.
+const REPLAY_BUFFER_LEN: usize = 256;
+/// How long a session outlives its socket, waiting to be resumed.
+const RESUME_WINDOW: Duration = Duration::from_secs(60);
+
+/// Registry of connected sessions and the guilds each of them is subscribed to.
+///
+/// Everything a session sends goes through its queue in the hub, including direct replies,
+/// so events published while handling a request always reach the client before its ack.
+/// The hub numbers those messages and keeps the most recent ones, which lets a session survive
+/// its socket for [`RESUME_WINDOW`] and be resumed on a new one.
+#[derive(Default)]
+pub struct Hub {
+    inner: Mutex<Inner>,
+    next_session_id: AtomicU64,
+}
+
+#[derive(Default)]
+struct Inner {
+    sessions: HashMap<SessionId, SessionEntry>,
+    guilds: HashMap<Snowflake, HashSet<SessionId>>,
+}
+
+struct SessionEntry {
+    user_id: Snowflake,
+    /// `None` while no socket is attached to the session.
+    tx: Option<mpsc::Sender<ServerMessage>>,
+    /// Bumped whenever a socket attaches, so a stale [`Connection`] can't detach its successor.
+    attachment: u64,
+    guilds: HashSet<Snowflake>,
+    next_seq: u64,
+    replay: VecDeque<(u64, ServerMessage)>,
+}
+
+/// A socket's attachment to a session in the [`Hub`]. Dropping it detaches the socket, and the
+/// session is removed unless it is resumed within [`RESUME_WINDOW`].
+pub struct Connection {
+    pub id: SessionId,
+    pub events: mpsc::Receiver<ServerMessage>,
+    attachment: u64,
+    hub: Arc<Hub>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.hub.detach(self.id, self.attachment);
+    }
+}
+
+impl Hub {
+    /// Locks the routing state. A panic while it was held is logged rather than passed on, so
+    /// one failed task does not take down every send that follows.
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|poisoned| {
+            tracing::error!("Hub state was poisoned by a panic, continuing with it");
+            poisoned.into_inner()
+        })
+    }
+
+    pub fn connect(self: &Arc<Self>, user_id: Snowflake, guild_ids: &[Snowflake]) -> Connection {
+        let id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, events) = mpsc::channel(SESSION_QUEUE_LEN);
+
+        let mut inner = self.lock();
+        inner.sessions.insert(
+            id,
+            SessionEntry {
+                user_id,
+                tx: Some(tx),
+                attachment: 0,
+                guilds: guild_ids.iter().copied().collect(),
+                next_seq: 1,
+                replay: VecDeque::new(),
+            },
+        );
+        for &guild_id in guild_ids {
+            inner.guilds.entry(guild_id).or_default().insert(id);
+        }
+
+        Connection {
+            id,
+            events,
+            attachment: 0,
+            hub: self.clone(),
+        }
+    }
+
+    /// Attaches a new socket to session `id` of `user_id`, returning the messages numbered after
+    /// `last_seq`. Fails if the session expired or those messages are no longer buffered.
+    pub fn resume(
+        self: &Arc<Self>,
+        id: SessionId,
+        user_id: Snowflake,
+        last_seq: u64,
+    ) -> Option<(Connection, Vec<ServerMessage>)> {
+        let mut inner = self.lock();
+        let session = inner.sessions.get_mut(&id)?;
+        if session.user_id != user_id || last_seq >= session.next_seq {
+            return None;
+        }
+        let oldest = session
+            .replay
+            .front()
+            .map_or(session.next_seq, |(seq, _)| *seq);
+        if oldest > last_seq + 1 {
+            return None;
+        }
+
+        let missed = session
+            .replay
+            .iter()
+            .filter(|(seq, _)| *seq > last_seq)
+            .map(|(_, msg)| msg.clone())
+            .collect();
+        let (tx, events) = mpsc::channel(SESSION_QUEUE_LEN);
+        session.tx = Some(tx);
+        session.attachment += 1;
+
+        let conn = Connection {
+            id,
+            events,
+            attachment: session.attachment,
+            hub: self.clone(),
+        };
+        Some((conn, missed))
+    }
+
+    /// Counts sessions with a socket attached and sessions waiting to be resumed.
+    pub fn session_counts(&self) -> (usize, usize) {
+        let inner = self.lock();
+        let attached = inner
+            .sessions
+            .values()
+            .filter(|session| session.tx.is_some())
+            .count();
+        (attached, inner.sessions.len() - attached)
+    }
+
+    /// Subscribes every session of `user_id` to `guild_id`, e.g. after they joined it.
+    pub fn subscribe_user(&self, user_id: Snowflake, guild_id: Snowflake) {
+        let mut inner = self.lock();
+        let Inner { sessions, guilds } = &mut *inner;
+        for (&id, session) in sessions.iter_mut() {
+            if session.user_id == user_id && session.guilds.insert(guild_id) {
+                guilds.entry(guild_id).or_default().insert(id);
+            }
+        }
+    }
+
+    pub fn send_to_session(&self, id: SessionId, msg: ServerMessage) {
+        let mut inner = self.lock();
+        inner.deliver([id], msg);
+    }
+
+    /// Sends `msg` to every session of `user_id`, including the user's other devices.
+    pub fn send_to_user(&self, user_id: Snowflake, msg: ServerMessage) {
+        let mut inner = self.lock();
+        let ids: Vec<_> = inner
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.user_id == user_id)
+            .map(|(&id, _)| id)
+            .collect();
+        inner.deliver(ids, msg);
+    }
+
+    /// Sends `msg` to every session subscribed to `guild_id`.
+    pub fn publish(&self, guild_id: Snowflake, msg: ServerMessage) {
+        let mut inner = self.lock();
+        let ids: Vec<_> = inner
+            .guilds
+            .get(&guild_id)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default();
+        inner.deliver(ids, msg);
+    }
+
+    fn detach(self: &Arc<Self>, id: SessionId, attachment: u64) {
+        let mut inner = self.lock();
+        let Some(session) = inner.sessions.get_mut(&id) else {
+            return;
+        };
+        if session.attachment != attachment {
+            return;
+        }
+        session.tx = None;
+
+        let hub = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(RESUME_WINDOW).await;
+            let mut inner = hub.lock();
+            if inner
+                .sessions
+                .get(&id)
+                .is_some_and(|session| session.attachment == attachment && session.tx.is_none())
+            {
+                inner.remove(id);
+            }
+        });
+    }
+}
+
+impl Inner {
+    fn deliver(&mut self, ids: impl IntoIterator<Item = SessionId>, msg: ServerMessage) {
+        let mut lagging = Vec::new();
+        for id in ids {
+            let Some(session) = self.sessions.get_mut(&id) else {
+                continue;
+            };
+            let seq = session.next_seq;
+            session.next_seq += 1;
+            let msg = ServerMessage::Sequenced {
+                seq,
+                message: Box::new(msg.clone()),
+            };
+            if session.replay.len() == REPLAY_BUFFER_LEN {
+                session.replay.pop_front();
+            }
+            session.replay.push_back((seq, msg.clone()));
+            if let Some(tx) = &session.tx
+                && tx.try_send(msg).is_err()
+            {
+                lagging.push(id);
+            }
+        }
+
+        // Dropping the sender ends the session's event stream, which closes its socket.
+        for id in lagging {
+            tracing::warn!("Dropping session {} with a full outbound queue", id);
+            self.remove(id);
+        }
+    }
+
+    fn remove(&mut self, id: SessionId) {
+        let Some(session) = self.sessions.remove(&id) else {
+            return;
+        };
+        for guild_id in session.guilds {
+            if let Some(ids) = self.guilds.get_mut(&guild_id) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.guilds.remove(&guild_id);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+mod auth;
+pub mod config;
+mod error;
+mod hub;
+pub mod logging;
+mod metrics;
+mod ratelimit;
+mod session;
+mod shutdown;
+pub mod storage;
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    Router,
+    extract::{State, WebSocketUpgrade},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+
+use crate::{
+    config::{Config, HeartbeatConfig, Limits},
+    hub::Hub,
+    metrics::Metrics,
+    ratelimit::RateLimiter,
+    shutdown::Shutdown,
+    storage::{MeteredStorage, Storage},
+};
+
+pub struct AppState {
+    storage: Arc<dyn Storage>,
+    hub: Arc<Hub>,
+    metrics: Arc<Metrics>,
+    heartbeat: HeartbeatConfig,
+    limits: Limits,
+    rate_limiter: RateLimiter,
+    shutdown: Shutdown,
+    shutdown_timeout: Duration,
+}
+
+impl AppState {
+    pub fn new(storage: Arc<dyn Storage>, config: &Config) -> Self {
+        let metrics = Arc::new(Metrics::default());
+        Self {
+            storage: Arc::new(MeteredStorage::new(storage, metrics.clone())),
+            hub: Arc::default(),
+            metrics,
+            heartbeat: config.heartbeat,
+            limits: config.limits,
+            rate_limiter: RateLimiter::new(config.rate_limits),
+            shutdown: Shutdown::default(),
+            shutdown_timeout: Duration::from_secs(config.shutdown_timeout_secs),
+        }
+    }
+
+    /// Stops accepting sessions, asks every running one to reconnect and waits for them to
+    /// finish, at most the configured shutdown timeout. Returns whether they all did.
+    pub async fn drain(&self, reason: &str) -> bool {
+        self.shutdown.drain(reason, self.shutdown_timeout).await
+    }
+}
+
+/// All routes of the server, shared by `main` and the integration tests.
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/", get(async || "Hello, World!"))
+        .route("/register", post(auth::register))
+        .route("/login", post(auth::login))
+        .route("/ws", get(ws_handler))
+        .route("/metrics", get(metrics::metrics))
+        .route("/healthz", get(metrics::healthz))
+        .route("/readyz", get(metrics::readyz))
+        .with_state(state)
+}
+
+async fn ws_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    ws_upgrade: WebSocketUpgrade,
+) -> Response {
+    if state.shutdown.is_draining() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    let user = match auth::authenticate(&state, &headers).await {
+        Ok(user) => user,
+        Err(status) => return status.into_response(),
+    };
+    ws_upgrade
+        .max_frame_size(state.limits.max_frame_bytes)
+        .max_message_size(state.limits.max_frame_bytes)
+        .on_upgrade(|ws| session::run(state, ws, user))
+        .into_response()
+}
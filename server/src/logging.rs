@@ -0,0 +1,74 @@
+//! Installs a [`tracing_subscriber::fmt`] subscriber that writes one line per event to stderr,
+//! either human readable or as JSON, along with the spans the event happened in.
+
+use std::{error::Error, str::FromStr};
+
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected pretty or json".to_string()),
+        }
+    }
+}
+
+/// Which events are logged, in [`EnvFilter`] syntax such as `info,server::hub=debug,sqlx=warn`:
+/// a default level followed by levels for targets and their submodules.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Filter(String);
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self("info".to_string())
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// Checks the directives up front, so a typo is a configuration error instead of being
+    /// ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EnvFilter::builder()
+            .parse(s)
+            .map_err(|err| err.to_string())?;
+        Ok(Self(s.to_string()))
+    }
+}
+
+pub fn init(filter: Filter, format: LogFormat) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::builder().parse(&filter.0)?)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Pretty => subscriber.try_init(),
+        LogFormat::Json => subscriber
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .try_init(),
+    }
+}
@@ -1,57 +1,92 @@
-mod db;
+use std::{process::ExitCode, sync::Arc};
 
-use std::{env, sync::Arc};
-
-use axum::{
-    Router,
-    extract::{State, WebSocketUpgrade, ws::Message},
-    response::IntoResponse,
-    routing::get,
-};
-
-use crate::db::Database;
-
-pub struct AppState {
-    db: Database,
-}
+use server::{AppState, config::Config, logging, storage};
 
 #[tokio::main]
-async fn main() {
-    dotenvy::dotenv().unwrap();
-    let url = env::var("DATABASE_URL").expect("Missing DATABASE_URL");
-    let db = Database::connect(&url).await.unwrap();
+async fn main() -> ExitCode {
+    if std::env::args().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{}", server::config::USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Invalid configuration: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(err) = logging::init(config.log.filter.clone(), config.log.format) {
+        eprintln!("Cannot install the logger: {}", err);
+        return ExitCode::FAILURE;
+    }
 
-    let app_state = Arc::new(AppState { db });
+    if let Err(err) = std::fs::create_dir_all(&config.upload_dir) {
+        eprintln!(
+            "Cannot create upload directory {}: {}",
+            config.upload_dir.display(),
+            err
+        );
+        return ExitCode::FAILURE;
+    }
+    let storage = match storage::open(&config.database, config.worker_id).await {
+        Ok(storage) => storage,
+        Err(err) => {
+            eprintln!("Cannot open storage: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let router = Router::new()
-        .route("/", get(async || "Hello, World!"))
-        .route("/ws", get(ws_handler))
-        .with_state(app_state);
+    let app_state = Arc::new(AppState::new(storage, &config));
+    let router = server::router(app_state.clone());
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, router).await.unwrap();
+    let listener = match tokio::net::TcpListener::bind(config.bind).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Cannot listen on {}: {}", config.bind, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let server = axum::serve(listener, router).with_graceful_shutdown(async move {
+        let signal = shutdown_signal().await;
+        tracing::info!("Received {}, draining sessions", signal);
+        if !app_state.drain("the server is shutting down").await {
+            tracing::warn!("Some sessions did not finish in time");
+        }
+    });
+    if let Err(err) = server.await {
+        eprintln!("Server error: {}", err);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
 }
 
-async fn ws_handler(
-    State(state): State<Arc<AppState>>,
-    ws_upgrade: WebSocketUpgrade,
-) -> impl IntoResponse {
-    ws_upgrade.on_upgrade(|mut ws| async move {
-        while let Some(msg) = ws.recv().await {
-            let msg = match msg {
-                Ok(msg) => msg,
-                Err(err) => {
-                    tracing::warn!("Websocket recv error: {}", err);
-                    break;
-                }
-            };
-
-            match msg {
-                Message::Text(text) => {
-                    ws.send(Message::Text(text)).await.unwrap();
-                }
-                Message::Binary(_) | Message::Ping(_) | Message::Pong(_) | Message::Close(_) => {}
+/// Resolves with the name of the first SIGINT or SIGTERM received.
+async fn shutdown_signal() -> &'static str {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Cannot listen for SIGINT: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Cannot listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
             }
         }
-    })
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => "SIGINT",
+        _ = terminate => "SIGTERM",
+    }
 }
@@ -0,0 +1,231 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::State,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppState, storage::PoolStatus};
+
+/// Upper bounds of the storage latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Counters the server keeps for `GET /metrics`. Gauges such as the number of sessions are
+/// read from their owners when scraped instead.
+#[derive(Default)]
+pub struct Metrics {
+    messages_created: AtomicU64,
+    decode_failures: AtomicU64,
+    client_messages: Mutex<BTreeMap<&'static str, u64>>,
+    rate_limited: Mutex<BTreeMap<&'static str, u64>>,
+    queries: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket of [`LATENCY_BUCKETS`], not cumulative.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    /// Counts a decoded [`common::ClientMessage`] by its [`common::ClientMessage::name`].
+    pub fn client_message(&self, name: &'static str) {
+        *lock(&self.client_messages).entry(name).or_default() += 1;
+    }
+
+    /// Counts a request rejected by the rate limiter, by category.
+    pub fn rate_limited(&self, category: &'static str) {
+        *lock(&self.rate_limited).entry(category).or_default() += 1;
+    }
+
+    pub fn decode_failure(&self) {
+        self.decode_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_created(&self) {
+        self.messages_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records how long a storage operation took.
+    pub fn query(&self, operation: &'static str, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let mut queries = lock(&self.queries);
+        let histogram = queries.entry(operation).or_default();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&bound| secs <= bound) {
+            histogram.buckets[i] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += secs;
+    }
+
+    /// Renders everything in the Prometheus text exposition format.
+    fn render(&self, sessions: (usize, usize), pool: Option<PoolStatus>) -> String {
+        let mut out = String::new();
+        let (attached, detached) = sessions;
+        let name = "chat_sessions";
+        describe(
+            &mut out,
+            name,
+            "gauge",
+            "Sessions by whether a socket is attached or they wait to be resumed.",
+        );
+        sample(&mut out, name, "state=\"attached\"", attached);
+        sample(&mut out, name, "state=\"detached\"", detached);
+
+        let name = "chat_messages_created_total";
+        describe(&mut out, name, "counter", "Chat messages sent by users.");
+        sample(
+            &mut out,
+            name,
+            "",
+            self.messages_created.load(Ordering::Relaxed),
+        );
+
+        let name = "chat_decode_failures_total";
+        describe(
+            &mut out,
+            name,
+            "counter",
+            "Websocket frames that did not decode as a client message.",
+        );
+        sample(
+            &mut out,
+            name,
+            "",
+            self.decode_failures.load(Ordering::Relaxed),
+        );
+
+        let name = "chat_client_messages_total";
+        describe(
+            &mut out,
+            name,
+            "counter",
+            "Decoded client messages by kind.",
+        );
+        for (kind, count) in lock(&self.client_messages).iter() {
+            sample(&mut out, name, &format!("kind=\"{}\"", kind), count);
+        }
+
+        let name = "chat_rate_limited_total";
+        describe(
+            &mut out,
+            name,
+            "counter",
+            "Requests rejected by the rate limiter, by category.",
+        );
+        for (category, count) in lock(&self.rate_limited).iter() {
+            sample(&mut out, name, &format!("category=\"{}\"", category), count);
+        }
+
+        let name = "chat_storage_query_duration_seconds";
+        describe(
+            &mut out,
+            name,
+            "histogram",
+            "Latency of storage operations.",
+        );
+        for (operation, histogram) in lock(&self.queries).iter() {
+            let bucket = format!("{}_bucket", name);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let labels = format!("operation=\"{}\",le=\"{}\"", operation, bound);
+                sample(&mut out, &bucket, &labels, cumulative);
+            }
+            let labels = format!("operation=\"{}\"", operation);
+            let inf = format!("{},le=\"+Inf\"", labels);
+            sample(&mut out, &bucket, &inf, histogram.count);
+            sample(&mut out, &format!("{}_sum", name), &labels, histogram.sum);
+            sample(
+                &mut out,
+                &format!("{}_count", name),
+                &labels,
+                histogram.count,
+            );
+        }
+
+        if let Some(pool) = pool {
+            let name = "chat_db_pool_connections";
+            describe(
+                &mut out,
+                name,
+                "gauge",
+                "Open database connections by whether they are in use.",
+            );
+            sample(
+                &mut out,
+                name,
+                "state=\"busy\"",
+                pool.size.saturating_sub(pool.idle),
+            );
+            sample(&mut out, name, "state=\"idle\"", pool.idle);
+
+            let name = "chat_db_pool_max_connections";
+            describe(
+                &mut out,
+                name,
+                "gauge",
+                "Size limit of the database connection pool.",
+            );
+            sample(&mut out, name, "", pool.max);
+        }
+        out
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl Display) {
+    let _ = match labels.is_empty() {
+        true => writeln!(out, "{} {}", name, value),
+        false => writeln!(out, "{}{{{}}} {}", name, labels, value),
+    };
+}
+
+pub async fn metrics(State(state): State<Arc<AppState>>) -> Response {
+    let body = state
+        .metrics
+        .render(state.hub.session_counts(), state.storage.pool_status());
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
+
+/// Liveness: the server answers and can reach its database.
+pub async fn healthz(State(state): State<Arc<AppState>>) -> Response {
+    match state.storage.ping().await {
+        Ok(()) => "ok".into_response(),
+        Err(err) => {
+            tracing::warn!("Health check failed: {}", err);
+            (StatusCode::SERVICE_UNAVAILABLE, "database unreachable").into_response()
+        }
+    }
+}
+
+/// Readiness: the server is healthy and not shutting down, so it should be sent traffic.
+pub async fn readyz(state: State<Arc<AppState>>) -> Response {
+    if state.shutdown.is_draining() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down").into_response();
+    }
+    healthz(state).await
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use common::{Request, Snowflake};
+
+use crate::config::{RateLimit, RateLimits, RequestLimits};
+
+/// Per-user buckets kept before full ones, which are no different from fresh ones, are first
+/// dropped. Later sweeps wait until the map has doubled, so they stay off most requests.
+const MAX_USER_BUCKETS: usize = 4096;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Category {
+    SendMessage,
+    CreateGuild,
+    JoinGuild,
+    Other,
+}
+
+impl Category {
+    pub fn of(request: &Request) -> Self {
+        match request {
+            Request::SendMessage(_) | Request::EditMessage(_) | Request::AddReaction(_) => {
+                Category::SendMessage
+            }
+            Request::CreateGuild(_) => Category::CreateGuild,
+            Request::JoinGuild(_) | Request::PreviewInvite(_) => Category::JoinGuild,
+            Request::FetchMessages(_)
+            | Request::CreateInvite(_)
+            | Request::RevokeInvite(_)
+            | Request::MarkRead(_)
+            | Request::DeleteMessage(_)
+            | Request::FetchMessageRevisions(_)
+            | Request::PinMessage(_) => Category::Other,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::SendMessage => "send_message",
+            Category::CreateGuild => "create_guild",
+            Category::JoinGuild => "join_guild",
+            Category::Other => "other",
+        }
+    }
+
+    fn limit(self, limits: &RequestLimits) -> RateLimit {
+        match self {
+            Category::SendMessage => limits.send_message,
+            Category::CreateGuild => limits.create_guild,
+            Category::JoinGuild => limits.join_guild,
+            Category::Other => limits.other,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        }
+    }
+
+    /// Refills the bucket up to `now` and returns how long until it holds a whole token.
+    fn wait(&mut self, limit: RateLimit, now: Instant) -> Duration {
+        let per_sec = f64::from(limit.per_minute) / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_sec).min(f64::from(limit.burst));
+        self.updated = now;
+        match self.tokens >= 1.0 {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64((1.0 - self.tokens) / per_sec),
+        }
+    }
+}
+
+/// The buckets of a single session, owned by its task.
+pub struct SessionBuckets {
+    frames: Bucket,
+    requests: HashMap<Category, Bucket>,
+}
+
+/// Applies [`RateLimits`]. Per-user buckets live here, shared by all sessions of the user.
+pub struct RateLimiter {
+    limits: RateLimits,
+    users: Mutex<UserBuckets>,
+}
+
+#[derive(Default)]
+struct UserBuckets {
+    buckets: HashMap<(Snowflake, Category), Bucket>,
+    /// Size at which full buckets get swept next.
+    sweep_at: usize,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            users: Mutex::default(),
+        }
+    }
+
+    pub fn session(&self) -> SessionBuckets {
+        SessionBuckets {
+            frames: Bucket::full(self.limits.frames, Instant::now()),
+            requests: HashMap::new(),
+        }
+    }
+
+    /// Counts an inbound frame. Returns `false` if the session sends them faster than allowed.
+    pub fn frame(&self, session: &mut SessionBuckets) -> bool {
+        let limit = self.limits.frames;
+        if !session.frames.wait(limit, Instant::now()).is_zero() {
+            return false;
+        }
+        session.frames.tokens -= 1.0;
+        true
+    }
+
+    /// Takes a token from both the session's and the user's bucket for `category`, or returns
+    /// how long until both have one. Nothing is taken from either if one of them is empty.
+    pub fn request(
+        &self,
+        session: &mut SessionBuckets,
+        user_id: Snowflake,
+        category: Category,
+    ) -> Result<(), Duration> {
+        let now = Instant::now();
+        let session_limit = category.limit(&self.limits.session);
+        let user_limit = category.limit(&self.limits.user);
+
+        let mut users = self.users.lock().unwrap_or_else(|err| err.into_inner());
+        if users.buckets.len() >= users.sweep_at.max(MAX_USER_BUCKETS) {
+            users.buckets.retain(|&(_, category), bucket| {
+                let limit = category.limit(&self.limits.user);
+                bucket.wait(limit, now);
+                bucket.tokens < f64::from(limit.burst)
+            });
+            users.sweep_at = users.buckets.len() * 2;
+        }
+        let user = users
+            .buckets
+            .entry((user_id, category))
+            .or_insert_with(|| Bucket::full(user_limit, now));
+        let session = session
+            .requests
+            .entry(category)
+            .or_insert_with(|| Bucket::full(session_limit, now));
+
+        let wait = session
+            .wait(session_limit, now)
+            .max(user.wait(user_limit, now));
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        session.tokens -= 1.0;
+        user.tokens -= 1.0;
+        Ok(())
+    }
+}
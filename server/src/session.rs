@@ -0,0 +1,729 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::extract::ws::{CloseFrame, Message, WebSocket, close_code};
+use common::{
+    Ack, AddReaction, ClientMessage, CreateGuild, CreateInvite, DeleteMessage, EditMessage,
+    ErrorCode, FetchMessageRevisions, FetchMessages, GuildMember, JoinGuild, JoinGuildByCode,
+    MAX_FETCH_MESSAGES, MarkRead, MemberJoin, MessageCreate, MessageDelete, MessageRevisions,
+    MessageUpdate, Messages, PROTOCOL_VERSION, PinMessage, PreviewInvite, ReactionAdd, Ready,
+    Refused, Request, Resume, Resumed, RevokeInvite, SendMessage, ServerMessage, Snowflake, User,
+    Welcome,
+};
+use rand::{Rng, distr::Alphanumeric};
+use tokio::time::MissedTickBehavior;
+use tracing::Span;
+
+use crate::{
+    AppState,
+    error::RequestError,
+    hub::Connection,
+    ratelimit::{Category, SessionBuckets},
+    storage::StorageError,
+};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tracing::instrument(name = "session", skip_all, fields(user_id = user.id, session_id))]
+pub async fn run(state: Arc<AppState>, mut ws: WebSocket, user: User) {
+    let _guard = state.shutdown.track_session();
+    let handshake = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake(&state, &mut ws, &user));
+    let mut conn = match handshake.await {
+        Ok(Some(Handshake::New(conn))) => match ready(&state, &user).await {
+            Ok(ready) => {
+                state
+                    .hub
+                    .send_to_session(conn.id, ServerMessage::Ready(ready));
+                conn
+            }
+            Err(err) => {
+                tracing::error!("Failed to load the state of {}: {}", user.name, err);
+                return;
+            }
+        },
+        Ok(Some(Handshake::Resumed(conn))) => conn,
+        Ok(None) => return,
+        Err(_) => {
+            tracing::warn!("Websocket handshake timed out");
+            return;
+        }
+    };
+
+    Span::current().record("session_id", conn.id);
+
+    let interval = state.heartbeat.interval();
+    let mut heartbeat = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut missed_heartbeats = 0;
+    let mut buckets = state.rate_limiter.session();
+
+    loop {
+        tokio::select! {
+            client_message = recv(&state, &mut ws) => {
+                let Some(client_message) = client_message else {
+                    break;
+                };
+                missed_heartbeats = 0;
+                if !state.rate_limiter.frame(&mut buckets) {
+                    tracing::warn!("Closing session {} of {} for flooding", conn.id, user.name);
+                    let frame = CloseFrame {
+                        code: close_code::POLICY,
+                        reason: "too many messages".into(),
+                    };
+                    let _ = ws.send(Message::Close(Some(frame))).await;
+                    break;
+                }
+                // Pongs skip the hub so they neither wait behind queued events nor take up
+                // room in the replay buffer.
+                if let Ok(ClientMessage::Ping { nonce }) = client_message {
+                    if send(&mut ws, ServerMessage::Pong { nonce }).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                let replies =
+                    handle_client_message(&state, &user, &mut buckets, client_message).await;
+                for reply in replies {
+                    state.hub.send_to_session(conn.id, reply);
+                }
+            }
+            _ = heartbeat.tick() => {
+                if missed_heartbeats >= state.heartbeat.max_missed {
+                    tracing::info!(
+                        "Closing session {} of {} after {} missed heartbeats",
+                        conn.id,
+                        user.name,
+                        missed_heartbeats
+                    );
+                    break;
+                }
+                missed_heartbeats += 1;
+                if send(&mut ws, ServerMessage::Heartbeat).await.is_err() {
+                    break;
+                }
+            }
+            event = conn.events.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                if send(&mut ws, event).await.is_err() {
+                    break;
+                }
+            }
+            reason = state.shutdown.draining() => {
+                drain(&mut ws, &mut conn, reason).await;
+                break;
+            }
+        }
+    }
+}
+
+/// Flushes whatever is queued for the session, then tells the client to reconnect and closes.
+async fn drain(ws: &mut WebSocket, conn: &mut Connection, reason: String) {
+    while let Ok(event) = conn.events.try_recv() {
+        if send(ws, event).await.is_err() {
+            return;
+        }
+    }
+    let reconnect = ServerMessage::Reconnect {
+        reason: reason.clone(),
+    };
+    if send(ws, reconnect).await.is_ok() {
+        let frame = CloseFrame {
+            code: close_code::AWAY,
+            reason: reason.into(),
+        };
+        let _ = ws.send(Message::Close(Some(frame))).await;
+    }
+}
+
+/// Builds the snapshot a session that starts from scratch needs.
+async fn ready(state: &AppState, user: &User) -> Result<Ready, StorageError> {
+    let mut guilds = Vec::new();
+    for guild_id in state.storage.guild_ids_for_user(user.id).await? {
+        if let Some(guild) = state.storage.load_guild(guild_id).await? {
+            guilds.push(guild);
+        }
+    }
+    Ok(Ready {
+        user: user.clone(),
+        guilds,
+        read_states: state.storage.read_states(user.id).await?,
+    })
+}
+
+/// Handles a message in its own span, which records the request and how long it took.
+#[tracing::instrument(
+    name = "message",
+    skip_all,
+    fields(kind, request_id, guild_id, latency_ms)
+)]
+async fn handle_client_message(
+    state: &AppState,
+    user: &User,
+    buckets: &mut SessionBuckets,
+    client_message: Result<ClientMessage, bincode::error::DecodeError>,
+) -> Vec<ServerMessage> {
+    let started = Instant::now();
+    let span = Span::current();
+    let replies = match client_message {
+        Ok(ClientMessage::Ping { .. }) => Vec::new(),
+        Ok(ClientMessage::Hello(_) | ClientMessage::Resume(_)) => {
+            tracing::warn!("Ignoring handshake message in an established session");
+            Vec::new()
+        }
+        Ok(ClientMessage::Request {
+            request_id,
+            request,
+        }) => {
+            span.record("kind", request.name());
+            span.record("request_id", request_id);
+            let category = Category::of(&request);
+            match state.rate_limiter.request(buckets, user.id, category) {
+                Ok(()) => match handle_request(state, user, request).await {
+                    Ok(mut replies) => {
+                        replies.push(ServerMessage::Ack(Ack { request_id }));
+                        replies
+                    }
+                    Err(err) => {
+                        tracing::debug!("Request failed: {}", err.message);
+                        vec![err.into_message(Some(request_id))]
+                    }
+                },
+                Err(retry_after) => {
+                    tracing::debug!("Rate limited for {:?}", retry_after);
+                    state.metrics.rate_limited(category.name());
+                    vec![ServerMessage::RateLimited {
+                        request_id,
+                        retry_after_ms: retry_after.as_millis().try_into().unwrap_or(u32::MAX),
+                    }]
+                }
+            }
+        }
+        Err(err) => {
+            tracing::warn!("Failed to decode client message: {}", err);
+            let err = RequestError::new(ErrorCode::InvalidRequest, "malformed message");
+            vec![err.into_message(None)]
+        }
+    };
+    span.record("latency_ms", started.elapsed().as_secs_f64() * 1000.0);
+    tracing::debug!("Handled client message");
+    replies
+}
+
+enum Handshake {
+    New(Connection),
+    Resumed(Connection),
+}
+
+/// Waits for the client's [`common::Hello`] and answers it with either [`Welcome`] or [`Refused`].
+/// A [`Resume`] is answered with [`Resumed`] and the missed messages, or with
+/// [`ServerMessage::InvalidSession`] after which the client may try again.
+async fn handshake(state: &AppState, ws: &mut WebSocket, user: &User) -> Option<Handshake> {
+    let reason = loop {
+        match recv(state, ws).await? {
+            Ok(ClientMessage::Hello(hello)) if hello.protocol_version == PROTOCOL_VERSION => {
+                let guild_ids = match state.storage.guild_ids_for_user(user.id).await {
+                    Ok(guild_ids) => guild_ids,
+                    Err(err) => {
+                        tracing::error!("Failed to load guilds of {}: {}", user.name, err);
+                        return None;
+                    }
+                };
+                let conn = state.hub.connect(user.id, &guild_ids);
+                let welcome = ServerMessage::Welcome(Welcome {
+                    protocol_version: PROTOCOL_VERSION,
+                    server_name: format!(
+                        "{} {}",
+                        env!("CARGO_PKG_NAME"),
+                        env!("CARGO_PKG_VERSION")
+                    ),
+                    capabilities: Vec::new(),
+                    session_id: conn.id,
+                    heartbeat_interval_ms: u32::try_from(state.heartbeat.interval().as_millis())
+                        .unwrap_or(u32::MAX),
+                });
+                send(ws, welcome).await.ok()?;
+                tracing::debug!("Client {} connected as {}", hello.client_name, user.name);
+                return Some(Handshake::New(conn));
+            }
+            Ok(ClientMessage::Hello(hello)) => {
+                break format!(
+                    "Client speaks protocol version {} but the server requires version {}",
+                    hello.protocol_version, PROTOCOL_VERSION
+                );
+            }
+            Ok(ClientMessage::Resume(Resume {
+                session_id,
+                last_seq,
+            })) => {
+                let Some((conn, missed)) = state.hub.resume(session_id, user.id, last_seq) else {
+                    tracing::debug!("Session {} of {} can't be resumed", session_id, user.name);
+                    send(ws, ServerMessage::InvalidSession).await.ok()?;
+                    continue;
+                };
+                tracing::debug!(
+                    "Resumed session {} of {}, replaying {} messages",
+                    session_id,
+                    user.name,
+                    missed.len()
+                );
+                send(ws, ServerMessage::Resumed(Resumed { session_id }))
+                    .await
+                    .ok()?;
+                for msg in missed {
+                    send(ws, msg).await.ok()?;
+                }
+                return Some(Handshake::Resumed(conn));
+            }
+            Ok(_) => break "Expected Hello as the first message".to_string(),
+            Err(_) => break "Could not decode Hello, the client is incompatible".to_string(),
+        }
+    };
+
+    tracing::warn!("Refusing client: {}", reason);
+    let refused = ServerMessage::Refused(Refused {
+        protocol_version: PROTOCOL_VERSION,
+        reason: reason.clone(),
+    });
+    if send(ws, refused).await.is_ok() {
+        let frame = CloseFrame {
+            code: close_code::PROTOCOL,
+            reason: reason.into(),
+        };
+        let _ = ws.send(Message::Close(Some(frame))).await;
+    }
+    None
+}
+
+/// Returns the next binary frame decoded as a [`ClientMessage`], or `None` once the socket is done.
+async fn recv(
+    state: &AppState,
+    ws: &mut WebSocket,
+) -> Option<Result<ClientMessage, bincode::error::DecodeError>> {
+    while let Some(msg) = ws.recv().await {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(err) => {
+                tracing::warn!("Websocket recv error: {}", err);
+                return None;
+            }
+        };
+
+        match msg {
+            Message::Binary(data) => {
+                let client_message = ClientMessage::decode(&data);
+                match &client_message {
+                    Ok(client_message) => state.metrics.client_message(client_message.name()),
+                    Err(_) => state.metrics.decode_failure(),
+                }
+                return Some(client_message);
+            }
+            Message::Close(_) => return None,
+            Message::Text(_) | Message::Ping(_) | Message::Pong(_) => {}
+        }
+    }
+    None
+}
+
+async fn send(ws: &mut WebSocket, msg: ServerMessage) -> Result<(), ()> {
+    let bytes = match msg.encode() {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::error!("Failed to encode server message: {}", err);
+            return Ok(());
+        }
+    };
+
+    ws.send(Message::Binary(bytes.into())).await.map_err(|err| {
+        tracing::warn!("Websocket send error: {}", err);
+    })
+}
+
+const INVITE_CODE_LEN: usize = 8;
+/// Longest emoji accepted as a reaction, in characters. Sequences joined with zero width
+/// joiners, such as family emoji, take up to 11.
+const MAX_EMOJI_LEN: usize = 16;
+/// Most different emoji a single message can be reacted with.
+const MAX_REACTIONS: usize = 20;
+
+/// Handles a request, returning replies for the requesting session only. Events for other
+/// sessions are published through the hub before the replies are queued.
+async fn handle_request(
+    state: &AppState,
+    user: &User,
+    request: Request,
+) -> Result<Vec<ServerMessage>, RequestError> {
+    match request {
+        Request::CreateGuild(CreateGuild { name, icon_url }) => {
+            let name = name.trim().to_string();
+            if name.is_empty() || name.chars().count() > state.limits.max_guild_name_len {
+                return Err(RequestError::new(
+                    ErrorCode::InvalidRequest,
+                    format!(
+                        "guild name must be 1 to {} characters",
+                        state.limits.max_guild_name_len
+                    ),
+                ));
+            }
+
+            let id = state
+                .storage
+                .create_guild(user.id, &name, &icon_url)
+                .await?;
+            Span::current().record("guild_id", id);
+            join_guild(state, user, id).await?;
+            Ok(Vec::new())
+        }
+        Request::SendMessage(SendMessage {
+            channel_id,
+            content,
+            nonce,
+            reply_to,
+        }) => {
+            validate_content(state, &content)?;
+
+            let guild_id = channel_guild(state, user, channel_id).await?;
+            if let Some(reply_to) = reply_to {
+                find_message(state, channel_id, reply_to).await?;
+            }
+            let message = state
+                .storage
+                .create_message(channel_id, user.id, &content, reply_to)
+                .await?;
+            state.metrics.message_created();
+            state.hub.publish(
+                guild_id,
+                ServerMessage::MessageCreate(MessageCreate {
+                    guild_id,
+                    channel_id,
+                    message,
+                    nonce: Some(nonce),
+                }),
+            );
+            Ok(Vec::new())
+        }
+        Request::FetchMessages(FetchMessages {
+            channel_id,
+            cursor,
+            limit,
+        }) => {
+            let guild_id = channel_guild(state, user, channel_id).await?;
+            let limit = limit.clamp(1, MAX_FETCH_MESSAGES);
+            let messages = state
+                .storage
+                .fetch_messages(channel_id, cursor, limit)
+                .await?;
+            Ok(vec![ServerMessage::Messages(Messages {
+                guild_id,
+                channel_id,
+                cursor,
+                messages,
+            })])
+        }
+        Request::CreateInvite(CreateInvite {
+            guild_id,
+            max_age_secs,
+            max_uses,
+        }) => {
+            Span::current().record("guild_id", guild_id);
+            if !state.storage.is_member(guild_id, user.id).await? {
+                return Err(RequestError::new(ErrorCode::NotFound, "unknown guild"));
+            }
+            let code: String = rand::rng()
+                .sample_iter(Alphanumeric)
+                .take(INVITE_CODE_LEN)
+                .map(char::from)
+                .collect();
+            let invite = state
+                .storage
+                .create_invite(&code, guild_id, user.id, max_age_secs, max_uses)
+                .await?;
+            Ok(vec![ServerMessage::InviteCreated(invite)])
+        }
+        Request::PreviewInvite(PreviewInvite { code }) => {
+            let preview = state
+                .storage
+                .invite_preview(&code)
+                .await?
+                .ok_or_else(invalid_invite)?;
+            Ok(vec![ServerMessage::InvitePreview(preview)])
+        }
+        Request::JoinGuild(JoinGuildByCode { code }) => {
+            let (guild_id, joined) = state
+                .storage
+                .use_invite(&code, user.id)
+                .await?
+                .ok_or_else(invalid_invite)?;
+            Span::current().record("guild_id", guild_id);
+            if joined {
+                state.hub.publish(
+                    guild_id,
+                    ServerMessage::MemberJoin(MemberJoin {
+                        guild_id,
+                        user_id: user.id,
+                        member: GuildMember {
+                            name: user.name.clone(),
+                            avatar_url: user.avatar_url.clone(),
+                        },
+                    }),
+                );
+            }
+            join_guild(state, user, guild_id).await?;
+            Ok(Vec::new())
+        }
+        Request::MarkRead(MarkRead {
+            channel_id,
+            message_id,
+        }) => {
+            channel_guild(state, user, channel_id).await?;
+            let read_state = state
+                .storage
+                .mark_read(user.id, channel_id, message_id)
+                .await?
+                .ok_or_else(unknown_message)?;
+            state
+                .hub
+                .send_to_user(user.id, ServerMessage::ReadState(read_state));
+            Ok(Vec::new())
+        }
+        Request::RevokeInvite(RevokeInvite { code }) => {
+            if !state.storage.revoke_invite(&code, user.id).await? {
+                return Err(RequestError::new(
+                    ErrorCode::NotPermitted,
+                    "only the invite's creator or the guild owner can revoke it",
+                ));
+            }
+            Ok(Vec::new())
+        }
+        Request::EditMessage(EditMessage {
+            channel_id,
+            message_id,
+            content,
+        }) => {
+            validate_content(state, &content)?;
+            let guild_id = channel_guild(state, user, channel_id).await?;
+            let message = find_message(state, channel_id, message_id).await?;
+            if message.author_id != user.id {
+                return Err(RequestError::new(
+                    ErrorCode::NotPermitted,
+                    "only the author can edit a message",
+                ));
+            }
+            let message = state
+                .storage
+                .edit_message(channel_id, message_id, &content)
+                .await?
+                .ok_or_else(unknown_message)?;
+            state.hub.publish(
+                guild_id,
+                ServerMessage::MessageUpdate(MessageUpdate {
+                    guild_id,
+                    channel_id,
+                    message,
+                }),
+            );
+            Ok(Vec::new())
+        }
+        Request::DeleteMessage(DeleteMessage {
+            channel_id,
+            message_id,
+        }) => {
+            let guild_id = channel_guild(state, user, channel_id).await?;
+            let message = find_message(state, channel_id, message_id).await?;
+            if message.author_id != user.id && !is_moderator(state, guild_id, user.id).await? {
+                return Err(RequestError::new(
+                    ErrorCode::NotPermitted,
+                    "only the author or a moderator can delete a message",
+                ));
+            }
+            if !state.storage.delete_message(channel_id, message_id).await? {
+                return Err(unknown_message());
+            }
+            state.hub.publish(
+                guild_id,
+                ServerMessage::MessageDelete(MessageDelete {
+                    guild_id,
+                    channel_id,
+                    message_id,
+                }),
+            );
+            Ok(Vec::new())
+        }
+        Request::FetchMessageRevisions(FetchMessageRevisions {
+            channel_id,
+            message_id,
+        }) => {
+            let guild_id = channel_guild(state, user, channel_id).await?;
+            if !is_moderator(state, guild_id, user.id).await? {
+                return Err(RequestError::new(
+                    ErrorCode::NotPermitted,
+                    "only moderators can view the edit history of messages",
+                ));
+            }
+            find_message(state, channel_id, message_id).await?;
+            let revisions = state.storage.message_revisions(message_id).await?;
+            Ok(vec![ServerMessage::MessageRevisions(MessageRevisions {
+                guild_id,
+                channel_id,
+                message_id,
+                revisions,
+            })])
+        }
+        Request::PinMessage(PinMessage {
+            channel_id,
+            message_id,
+            pinned,
+        }) => {
+            let guild_id = channel_guild(state, user, channel_id).await?;
+            if !is_moderator(state, guild_id, user.id).await? {
+                return Err(RequestError::new(
+                    ErrorCode::NotPermitted,
+                    "only moderators can pin messages",
+                ));
+            }
+            let message = state
+                .storage
+                .set_pinned(channel_id, message_id, pinned)
+                .await?
+                .ok_or_else(unknown_message)?;
+            state.hub.publish(
+                guild_id,
+                ServerMessage::MessageUpdate(MessageUpdate {
+                    guild_id,
+                    channel_id,
+                    message,
+                }),
+            );
+            Ok(Vec::new())
+        }
+        Request::AddReaction(AddReaction {
+            channel_id,
+            message_id,
+            emoji,
+        }) => {
+            let count = emoji.chars().count();
+            if count == 0
+                || count > MAX_EMOJI_LEN
+                || emoji.chars().any(|c| c.is_whitespace() || c.is_control())
+            {
+                return Err(RequestError::new(
+                    ErrorCode::InvalidRequest,
+                    format!(
+                        "reactions must be an emoji of 1 to {} characters",
+                        MAX_EMOJI_LEN
+                    ),
+                ));
+            }
+
+            let guild_id = channel_guild(state, user, channel_id).await?;
+            let message = find_message(state, channel_id, message_id).await?;
+            if message.reactions.len() >= MAX_REACTIONS
+                && !message.reactions.iter().any(|r| r.emoji == emoji)
+            {
+                return Err(RequestError::new(
+                    ErrorCode::InvalidRequest,
+                    format!(
+                        "messages can have at most {} different reactions",
+                        MAX_REACTIONS
+                    ),
+                ));
+            }
+            let added = state
+                .storage
+                .add_reaction(channel_id, message_id, user.id, &emoji)
+                .await?
+                .ok_or_else(unknown_message)?;
+            if added {
+                state.hub.publish(
+                    guild_id,
+                    ServerMessage::ReactionAdd(ReactionAdd {
+                        guild_id,
+                        channel_id,
+                        message_id,
+                        user_id: user.id,
+                        emoji,
+                    }),
+                );
+            }
+            Ok(Vec::new())
+        }
+    }
+}
+
+fn validate_content(state: &AppState, content: &str) -> Result<(), RequestError> {
+    if content.trim().is_empty() || content.chars().count() > state.limits.max_message_len {
+        return Err(RequestError::new(
+            ErrorCode::InvalidRequest,
+            format!(
+                "messages must be 1 to {} characters",
+                state.limits.max_message_len
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the guild of a text channel the user can see, and records it in the current span.
+async fn channel_guild(
+    state: &AppState,
+    user: &User,
+    channel_id: Snowflake,
+) -> Result<Snowflake, RequestError> {
+    let guild_id = state
+        .storage
+        .text_channel_guild(channel_id, user.id)
+        .await?
+        .ok_or_else(|| RequestError::new(ErrorCode::NotFound, "unknown channel"))?;
+    Span::current().record("guild_id", guild_id);
+    Ok(guild_id)
+}
+
+async fn find_message(
+    state: &AppState,
+    channel_id: Snowflake,
+    message_id: Snowflake,
+) -> Result<common::Message, RequestError> {
+    state
+        .storage
+        .find_message(channel_id, message_id)
+        .await?
+        .ok_or_else(unknown_message)
+}
+
+/// Same rule as [`common::Guild::is_moderator`].
+async fn is_moderator(
+    state: &AppState,
+    guild_id: Snowflake,
+    user_id: Snowflake,
+) -> Result<bool, RequestError> {
+    Ok(state.storage.guild_owner(guild_id).await? == Some(user_id))
+}
+
+fn unknown_message() -> RequestError {
+    RequestError::new(ErrorCode::NotFound, "unknown message")
+}
+
+/// Subscribes all of the user's sessions to a guild they just became a member of.
+async fn join_guild(
+    state: &AppState,
+    user: &User,
+    guild_id: Snowflake,
+) -> Result<(), RequestError> {
+    let guild = state
+        .storage
+        .load_guild(guild_id)
+        .await?
+        .ok_or_else(|| RequestError::new(ErrorCode::NotFound, "unknown guild"))?;
+    state.hub.subscribe_user(user.id, guild_id);
+    state
+        .hub
+        .send_to_user(user.id, ServerMessage::JoinGuild(JoinGuild { guild }));
+    Ok(())
+}
+
+fn invalid_invite() -> RequestError {
+    RequestError::new(ErrorCode::NotFound, "this invite is invalid or has expired")
+}
@@ -0,0 +1,57 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::watch;
+
+/// Coordinates a graceful shutdown. Once draining starts, new sessions are refused and running
+/// ones are asked to finish what they are doing, tell their client to reconnect and exit.
+pub struct Shutdown {
+    reason: watch::Sender<Option<String>>,
+    sessions: Arc<watch::Sender<usize>>,
+}
+
+/// Counts a session as running until dropped.
+pub struct SessionGuard(Arc<watch::Sender<usize>>);
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.0.send_modify(|sessions| *sessions -= 1);
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            reason: watch::channel(None).0,
+            sessions: Arc::new(watch::channel(0).0),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn is_draining(&self) -> bool {
+        self.reason.borrow().is_some()
+    }
+
+    pub fn track_session(&self) -> SessionGuard {
+        self.sessions.send_modify(|sessions| *sessions += 1);
+        SessionGuard(self.sessions.clone())
+    }
+
+    /// Resolves with the reason once draining has started.
+    pub async fn draining(&self) -> String {
+        let mut reason = self.reason.subscribe();
+        // Can't fail, the sender lives as long as `self`.
+        let _ = reason.wait_for(Option::is_some).await;
+        self.reason.borrow().clone().unwrap_or_default()
+    }
+
+    /// Starts draining and waits for every session to finish, at most `timeout`. Returns whether
+    /// they all did.
+    pub async fn drain(&self, reason: &str, timeout: Duration) -> bool {
+        self.reason.send_replace(Some(reason.to_string()));
+        let mut sessions = self.sessions.subscribe();
+        tokio::time::timeout(timeout, sessions.wait_for(|&sessions| sessions == 0))
+            .await
+            .is_ok_and(|finished| finished.is_ok())
+    }
+}
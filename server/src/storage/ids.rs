@@ -0,0 +1,58 @@
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use common::{
+    Snowflake,
+    snowflake::{self, EPOCH_MS, MAX_SEQUENCE},
+};
+
+/// Hands out [`Snowflake`] ids. Every server sharing a database needs its own worker id, ids are
+/// only unique per worker.
+pub struct IdGenerator {
+    worker: u16,
+    /// Millisecond and sequence of the last id handed out.
+    last: Mutex<(u64, u16)>,
+}
+
+impl IdGenerator {
+    /// Panics if `worker` is above [`snowflake::MAX_WORKER_ID`].
+    pub fn new(worker: u16) -> Self {
+        assert!(worker <= snowflake::MAX_WORKER_ID, "worker id out of range");
+        Self {
+            worker,
+            last: Mutex::new((EPOCH_MS, 0)),
+        }
+    }
+
+    pub fn next(&self) -> Snowflake {
+        let mut last = self
+            .last
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (last_ms, sequence) = *last;
+        // Never go back in time, even if the clock does, so ids keep increasing.
+        let now = now_ms().max(last_ms);
+        *last = match now > last_ms {
+            true => (now, 0),
+            false if sequence < MAX_SEQUENCE => (last_ms, sequence + 1),
+            // Used up this millisecond's sequence, borrow from the next one. The clock catches
+            // up unless more than 4096 ids are requested every millisecond.
+            false => (last_ms + 1, 0),
+        };
+        snowflake::compose(last.0, self.worker, last.1)
+    }
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
@@ -0,0 +1,575 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use common::{
+    Channel, ChannelKind, Guild, GuildMember, Invite, InvitePreview, Message, MessageCursor,
+    MessageRevision, ReadState, Snowflake, TextChannel, User, snowflake,
+};
+
+use super::{IdGenerator, Storage, StorageError};
+
+/// Keeps everything in process memory.
+#[derive(Default)]
+pub struct MemoryStorage {
+    ids: IdGenerator,
+    inner: Mutex<Inner>,
+}
+
+impl MemoryStorage {
+    pub fn new(ids: IdGenerator) -> Self {
+        Self {
+            ids,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Locks the data. A panic while it was held is logged rather than passed on, so one failed
+    /// request does not fail every storage call that follows.
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|poisoned| {
+            tracing::error!("Memory storage was poisoned by a panic, continuing with it");
+            poisoned.into_inner()
+        })
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    users: HashMap<Snowflake, (User, String)>,
+    /// Token hash to user id and expiry, in seconds since the epoch.
+    sessions: HashMap<String, (Snowflake, i64)>,
+    guilds: HashMap<Snowflake, GuildRecord>,
+    channels: HashMap<Snowflake, ChannelRecord>,
+    /// Messages of each channel, oldest first.
+    messages: HashMap<Snowflake, Vec<Message>>,
+    /// Earlier versions of each edited message, oldest first.
+    revisions: HashMap<Snowflake, Vec<MessageRevision>>,
+    invites: HashMap<String, InviteRecord>,
+    read_states: HashMap<(Snowflake, Snowflake), Snowflake>,
+}
+
+struct GuildRecord {
+    name: String,
+    icon_url: String,
+    owner_id: Snowflake,
+    members: BTreeSet<Snowflake>,
+}
+
+struct ChannelRecord {
+    guild_id: Snowflake,
+    name: String,
+    text: bool,
+    description: String,
+    position: i32,
+}
+
+struct InviteRecord {
+    invite: Invite,
+    revoked: bool,
+}
+
+impl Inner {
+    /// Returns the invite if it can still be used.
+    fn usable_invite(&mut self, code: &str) -> Option<&mut Invite> {
+        let record = self.invites.get_mut(code)?;
+        let invite = &mut record.invite;
+        let usable = !record.revoked
+            && invite
+                .expires_at
+                .is_none_or(|expires_at| expires_at > now())
+            && invite
+                .max_uses
+                .is_none_or(|max_uses| invite.uses < max_uses);
+        usable.then_some(invite)
+    }
+
+    fn message_mut(
+        &mut self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Option<&mut Message> {
+        self.messages
+            .get_mut(&channel_id)?
+            .iter_mut()
+            .find(|message| message.id == message_id)
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn ping(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str) -> Result<User, StorageError> {
+        let mut inner = self.lock();
+        if inner
+            .users
+            .values()
+            .any(|(user, _)| user.name.eq_ignore_ascii_case(username))
+        {
+            return Err(StorageError::Conflict);
+        }
+        let user = User {
+            id: self.ids.next(),
+            name: username.to_string(),
+            avatar_url: String::new(),
+        };
+        inner
+            .users
+            .insert(user.id, (user.clone(), password_hash.to_string()));
+        Ok(user)
+    }
+
+    async fn find_login(&self, username: &str) -> Result<Option<(User, String)>, StorageError> {
+        let inner = self.lock();
+        Ok(inner
+            .users
+            .values()
+            .find(|(user, _)| user.name.eq_ignore_ascii_case(username))
+            .cloned())
+    }
+
+    async fn create_session(
+        &self,
+        token_hash: &str,
+        user_id: Snowflake,
+        ttl_days: i32,
+    ) -> Result<(), StorageError> {
+        let expires_at = now() + i64::from(ttl_days) * 24 * 60 * 60;
+        let mut inner = self.lock();
+        inner
+            .sessions
+            .insert(token_hash.to_string(), (user_id, expires_at));
+        Ok(())
+    }
+
+    async fn find_session_user(&self, token_hash: &str) -> Result<Option<User>, StorageError> {
+        let inner = self.lock();
+        Ok(inner
+            .sessions
+            .get(token_hash)
+            .filter(|(_, expires_at)| *expires_at > now())
+            .and_then(|(user_id, _)| inner.users.get(user_id))
+            .map(|(user, _)| user.clone()))
+    }
+
+    async fn create_guild(
+        &self,
+        owner_id: Snowflake,
+        name: &str,
+        icon_url: &str,
+    ) -> Result<Snowflake, StorageError> {
+        let mut inner = self.lock();
+        let guild_id = self.ids.next();
+        inner.guilds.insert(
+            guild_id,
+            GuildRecord {
+                name: name.to_string(),
+                icon_url: icon_url.to_string(),
+                owner_id,
+                members: BTreeSet::from([owner_id]),
+            },
+        );
+        let channel_id = self.ids.next();
+        inner.channels.insert(
+            channel_id,
+            ChannelRecord {
+                guild_id,
+                name: "general".to_string(),
+                text: true,
+                description: String::new(),
+                position: 0,
+            },
+        );
+        Ok(guild_id)
+    }
+
+    async fn guild_owner(&self, guild_id: Snowflake) -> Result<Option<Snowflake>, StorageError> {
+        let inner = self.lock();
+        Ok(inner.guilds.get(&guild_id).map(|guild| guild.owner_id))
+    }
+
+    async fn load_guild(&self, guild_id: Snowflake) -> Result<Option<Guild>, StorageError> {
+        let inner = self.lock();
+        let Some(guild) = inner.guilds.get(&guild_id) else {
+            return Ok(None);
+        };
+
+        let mut channels: Vec<_> = inner
+            .channels
+            .iter()
+            .filter(|(_, channel)| channel.guild_id == guild_id)
+            .collect();
+        channels.sort_by_key(|&(&id, channel)| (channel.position, id));
+        let channels = channels
+            .into_iter()
+            .map(|(&id, channel)| Channel {
+                id,
+                name: channel.name.clone(),
+                kind: match channel.text {
+                    true => ChannelKind::Text(TextChannel {
+                        messages: Vec::new(),
+                        last_message_id: inner
+                            .messages
+                            .get(&id)
+                            .and_then(|messages| messages.last())
+                            .map(|message| message.id),
+                    }),
+                    false => ChannelKind::Voice,
+                },
+                description: channel.description.clone(),
+            })
+            .collect();
+
+        let members = guild
+            .members
+            .iter()
+            .filter_map(|user_id| inner.users.get(user_id))
+            .map(|(user, _)| {
+                let member = GuildMember {
+                    name: user.name.clone(),
+                    avatar_url: user.avatar_url.clone(),
+                };
+                (user.id, member)
+            })
+            .collect();
+
+        Ok(Some(Guild {
+            id: guild_id,
+            name: guild.name.clone(),
+            icon_url: guild.icon_url.clone(),
+            owner_id: Some(guild.owner_id),
+            channels,
+            members,
+        }))
+    }
+
+    async fn read_states(&self, user_id: Snowflake) -> Result<Vec<ReadState>, StorageError> {
+        let inner = self.lock();
+        Ok(inner
+            .read_states
+            .iter()
+            .filter(|((read_user_id, _), _)| *read_user_id == user_id)
+            .map(|(&(_, channel_id), &last_read_id)| ReadState {
+                channel_id,
+                last_read_id,
+            })
+            .collect())
+    }
+
+    async fn mark_read(
+        &self,
+        user_id: Snowflake,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<Option<ReadState>, StorageError> {
+        let mut inner = self.lock();
+        let exists = inner
+            .messages
+            .get(&channel_id)
+            .is_some_and(|messages| messages.iter().any(|message| message.id == message_id));
+        if !exists {
+            return Ok(None);
+        }
+        let last_read_id = inner.read_states.entry((user_id, channel_id)).or_default();
+        *last_read_id = (*last_read_id).max(message_id);
+        Ok(Some(ReadState {
+            channel_id,
+            last_read_id: *last_read_id,
+        }))
+    }
+
+    async fn is_member(
+        &self,
+        guild_id: Snowflake,
+        user_id: Snowflake,
+    ) -> Result<bool, StorageError> {
+        let inner = self.lock();
+        Ok(inner
+            .guilds
+            .get(&guild_id)
+            .is_some_and(|guild| guild.members.contains(&user_id)))
+    }
+
+    async fn create_invite(
+        &self,
+        code: &str,
+        guild_id: Snowflake,
+        creator_id: Snowflake,
+        max_age_secs: Option<u32>,
+        max_uses: Option<u32>,
+    ) -> Result<Invite, StorageError> {
+        let mut inner = self.lock();
+        if inner.invites.contains_key(code) {
+            return Err(StorageError::Conflict);
+        }
+        let invite = Invite {
+            code: code.to_string(),
+            guild_id,
+            creator_id,
+            expires_at: max_age_secs.map(|secs| now() + i64::from(secs)),
+            max_uses,
+            uses: 0,
+        };
+        inner.invites.insert(
+            code.to_string(),
+            InviteRecord {
+                invite: invite.clone(),
+                revoked: false,
+            },
+        );
+        Ok(invite)
+    }
+
+    async fn revoke_invite(&self, code: &str, user_id: Snowflake) -> Result<bool, StorageError> {
+        let mut inner = self.lock();
+        let Inner {
+            invites, guilds, ..
+        } = &mut *inner;
+        let Some(record) = invites.get_mut(code) else {
+            return Ok(false);
+        };
+        let owner_id = guilds
+            .get(&record.invite.guild_id)
+            .map(|guild| guild.owner_id);
+        if record.invite.creator_id != user_id && owner_id != Some(user_id) {
+            return Ok(false);
+        }
+        record.revoked = true;
+        Ok(true)
+    }
+
+    async fn invite_preview(&self, code: &str) -> Result<Option<InvitePreview>, StorageError> {
+        let mut inner = self.lock();
+        let Some(guild_id) = inner.usable_invite(code).map(|invite| invite.guild_id) else {
+            return Ok(None);
+        };
+        Ok(inner.guilds.get(&guild_id).map(|guild| InvitePreview {
+            code: code.to_string(),
+            guild_name: guild.name.clone(),
+            icon_url: guild.icon_url.clone(),
+            member_count: guild.members.len() as u32,
+        }))
+    }
+
+    async fn use_invite(
+        &self,
+        code: &str,
+        user_id: Snowflake,
+    ) -> Result<Option<(Snowflake, bool)>, StorageError> {
+        let mut inner = self.lock();
+        let Some(guild_id) = inner.usable_invite(code).map(|invite| invite.guild_id) else {
+            return Ok(None);
+        };
+        let Some(guild) = inner.guilds.get_mut(&guild_id) else {
+            return Ok(None);
+        };
+        let joined = guild.members.insert(user_id);
+        if joined && let Some(invite) = inner.usable_invite(code) {
+            invite.uses += 1;
+        }
+        Ok(Some((guild_id, joined)))
+    }
+
+    async fn guild_ids_for_user(&self, user_id: Snowflake) -> Result<Vec<Snowflake>, StorageError> {
+        let inner = self.lock();
+        Ok(inner
+            .guilds
+            .iter()
+            .filter(|(_, guild)| guild.members.contains(&user_id))
+            .map(|(&id, _)| id)
+            .collect())
+    }
+
+    async fn text_channel_guild(
+        &self,
+        channel_id: Snowflake,
+        user_id: Snowflake,
+    ) -> Result<Option<Snowflake>, StorageError> {
+        let inner = self.lock();
+        Ok(inner
+            .channels
+            .get(&channel_id)
+            .filter(|channel| channel.text)
+            .map(|channel| channel.guild_id)
+            .filter(|guild_id| {
+                inner
+                    .guilds
+                    .get(guild_id)
+                    .is_some_and(|guild| guild.members.contains(&user_id))
+            }))
+    }
+
+    async fn create_message(
+        &self,
+        channel_id: Snowflake,
+        author_id: Snowflake,
+        content: &str,
+        reply_to: Option<Snowflake>,
+    ) -> Result<Message, StorageError> {
+        // Take the id under the lock, so messages are pushed in id order.
+        let mut inner = self.lock();
+        let id = self.ids.next();
+        let message = Message {
+            id,
+            author_id,
+            content: content.to_string(),
+            created_at: snowflake::timestamp_ms(id) as i64,
+            edited_at: None,
+            reply_to,
+            pinned: false,
+            reactions: Vec::new(),
+        };
+        inner
+            .messages
+            .entry(channel_id)
+            .or_default()
+            .push(message.clone());
+        Ok(message)
+    }
+
+    async fn find_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<Option<Message>, StorageError> {
+        let inner = self.lock();
+        Ok(inner
+            .messages
+            .get(&channel_id)
+            .and_then(|messages| messages.iter().find(|message| message.id == message_id))
+            .cloned())
+    }
+
+    async fn edit_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        content: &str,
+    ) -> Result<Option<Message>, StorageError> {
+        let mut inner = self.lock();
+        let Inner {
+            messages,
+            revisions,
+            ..
+        } = &mut *inner;
+        let Some(message) = messages
+            .get_mut(&channel_id)
+            .and_then(|messages| messages.iter_mut().find(|message| message.id == message_id))
+        else {
+            return Ok(None);
+        };
+        let edited_at = now_ms();
+        let previous = MessageRevision {
+            content: std::mem::replace(&mut message.content, content.to_string()),
+            written_at: message.edited_at.unwrap_or(message.created_at),
+        };
+        message.edited_at = Some(edited_at);
+        revisions.entry(message_id).or_default().push(previous);
+        Ok(Some(message.clone()))
+    }
+
+    async fn delete_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<bool, StorageError> {
+        let mut inner = self.lock();
+        let Some(messages) = inner.messages.get_mut(&channel_id) else {
+            return Ok(false);
+        };
+        let count = messages.len();
+        messages.retain(|message| message.id != message_id);
+        if messages.len() == count {
+            return Ok(false);
+        }
+        inner.revisions.remove(&message_id);
+        Ok(true)
+    }
+
+    async fn set_pinned(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        pinned: bool,
+    ) -> Result<Option<Message>, StorageError> {
+        let mut inner = self.lock();
+        Ok(inner.message_mut(channel_id, message_id).map(|message| {
+            message.pinned = pinned;
+            message.clone()
+        }))
+    }
+
+    async fn add_reaction(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        user_id: Snowflake,
+        emoji: &str,
+    ) -> Result<Option<bool>, StorageError> {
+        let mut inner = self.lock();
+        Ok(inner
+            .message_mut(channel_id, message_id)
+            .map(|message| message.add_reaction(emoji, user_id)))
+    }
+
+    async fn message_revisions(
+        &self,
+        message_id: Snowflake,
+    ) -> Result<Vec<MessageRevision>, StorageError> {
+        let inner = self.lock();
+        Ok(inner
+            .revisions
+            .get(&message_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn fetch_messages(
+        &self,
+        channel_id: Snowflake,
+        cursor: MessageCursor,
+        limit: u32,
+    ) -> Result<Vec<Message>, StorageError> {
+        let inner = self.lock();
+        let Some(messages) = inner.messages.get(&channel_id) else {
+            return Ok(Vec::new());
+        };
+        // Messages are sorted by id, so every cursor is a window ending or starting at a split.
+        let split = |id: Snowflake| messages.partition_point(|message| message.id < id);
+        let before = |end: usize, limit: u32| &messages[end.saturating_sub(limit as usize)..end];
+        let after = |start: usize, limit: u32| {
+            &messages[start..(start + limit as usize).min(messages.len())]
+        };
+
+        let page = match cursor {
+            MessageCursor::Latest => before(messages.len(), limit).to_vec(),
+            MessageCursor::Before(id) => before(split(id), limit).to_vec(),
+            MessageCursor::After(id) => after(split(id.saturating_add(1)), limit).to_vec(),
+            MessageCursor::Around(id) => {
+                let pivot = split(id.saturating_add(1));
+                let mut page = before(pivot, limit.div_ceil(2)).to_vec();
+                page.extend_from_slice(after(pivot, limit / 2));
+                page
+            }
+        };
+        Ok(page)
+    }
+}
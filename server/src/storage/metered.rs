@@ -0,0 +1,280 @@
+use std::{sync::Arc, time::Instant};
+
+use async_trait::async_trait;
+use common::{
+    Guild, Invite, InvitePreview, Message, MessageCursor, MessageRevision, ReadState, Snowflake,
+    User,
+};
+
+use super::{PoolStatus, Storage, StorageError};
+use crate::metrics::Metrics;
+
+/// Wraps another storage and records the latency of every operation in [`Metrics`].
+pub struct MeteredStorage {
+    inner: Arc<dyn Storage>,
+    metrics: Arc<Metrics>,
+}
+
+impl MeteredStorage {
+    pub fn new(inner: Arc<dyn Storage>, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+
+    async fn timed<T>(&self, operation: &'static str, query: impl Future<Output = T>) -> T {
+        let started = Instant::now();
+        let result = query.await;
+        self.metrics.query(operation, started.elapsed());
+        result
+    }
+}
+
+#[async_trait]
+impl Storage for MeteredStorage {
+    async fn ping(&self) -> Result<(), StorageError> {
+        self.timed("ping", self.inner.ping()).await
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        self.inner.pool_status()
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str) -> Result<User, StorageError> {
+        self.timed(
+            "create_user",
+            self.inner.create_user(username, password_hash),
+        )
+        .await
+    }
+
+    async fn find_login(&self, username: &str) -> Result<Option<(User, String)>, StorageError> {
+        self.timed("find_login", self.inner.find_login(username))
+            .await
+    }
+
+    async fn create_session(
+        &self,
+        token_hash: &str,
+        user_id: Snowflake,
+        ttl_days: i32,
+    ) -> Result<(), StorageError> {
+        self.timed(
+            "create_session",
+            self.inner.create_session(token_hash, user_id, ttl_days),
+        )
+        .await
+    }
+
+    async fn find_session_user(&self, token_hash: &str) -> Result<Option<User>, StorageError> {
+        self.timed(
+            "find_session_user",
+            self.inner.find_session_user(token_hash),
+        )
+        .await
+    }
+
+    async fn create_guild(
+        &self,
+        owner_id: Snowflake,
+        name: &str,
+        icon_url: &str,
+    ) -> Result<Snowflake, StorageError> {
+        self.timed(
+            "create_guild",
+            self.inner.create_guild(owner_id, name, icon_url),
+        )
+        .await
+    }
+
+    async fn guild_owner(&self, guild_id: Snowflake) -> Result<Option<Snowflake>, StorageError> {
+        self.timed("guild_owner", self.inner.guild_owner(guild_id))
+            .await
+    }
+
+    async fn load_guild(&self, guild_id: Snowflake) -> Result<Option<Guild>, StorageError> {
+        self.timed("load_guild", self.inner.load_guild(guild_id))
+            .await
+    }
+
+    async fn read_states(&self, user_id: Snowflake) -> Result<Vec<ReadState>, StorageError> {
+        self.timed("read_states", self.inner.read_states(user_id))
+            .await
+    }
+
+    async fn mark_read(
+        &self,
+        user_id: Snowflake,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<Option<ReadState>, StorageError> {
+        self.timed(
+            "mark_read",
+            self.inner.mark_read(user_id, channel_id, message_id),
+        )
+        .await
+    }
+
+    async fn is_member(
+        &self,
+        guild_id: Snowflake,
+        user_id: Snowflake,
+    ) -> Result<bool, StorageError> {
+        self.timed("is_member", self.inner.is_member(guild_id, user_id))
+            .await
+    }
+
+    async fn create_invite(
+        &self,
+        code: &str,
+        guild_id: Snowflake,
+        creator_id: Snowflake,
+        max_age_secs: Option<u32>,
+        max_uses: Option<u32>,
+    ) -> Result<Invite, StorageError> {
+        self.timed(
+            "create_invite",
+            self.inner
+                .create_invite(code, guild_id, creator_id, max_age_secs, max_uses),
+        )
+        .await
+    }
+
+    async fn revoke_invite(&self, code: &str, user_id: Snowflake) -> Result<bool, StorageError> {
+        self.timed("revoke_invite", self.inner.revoke_invite(code, user_id))
+            .await
+    }
+
+    async fn invite_preview(&self, code: &str) -> Result<Option<InvitePreview>, StorageError> {
+        self.timed("invite_preview", self.inner.invite_preview(code))
+            .await
+    }
+
+    async fn use_invite(
+        &self,
+        code: &str,
+        user_id: Snowflake,
+    ) -> Result<Option<(Snowflake, bool)>, StorageError> {
+        self.timed("use_invite", self.inner.use_invite(code, user_id))
+            .await
+    }
+
+    async fn guild_ids_for_user(&self, user_id: Snowflake) -> Result<Vec<Snowflake>, StorageError> {
+        self.timed("guild_ids_for_user", self.inner.guild_ids_for_user(user_id))
+            .await
+    }
+
+    async fn text_channel_guild(
+        &self,
+        channel_id: Snowflake,
+        user_id: Snowflake,
+    ) -> Result<Option<Snowflake>, StorageError> {
+        self.timed(
+            "text_channel_guild",
+            self.inner.text_channel_guild(channel_id, user_id),
+        )
+        .await
+    }
+
+    async fn create_message(
+        &self,
+        channel_id: Snowflake,
+        author_id: Snowflake,
+        content: &str,
+        reply_to: Option<Snowflake>,
+    ) -> Result<Message, StorageError> {
+        self.timed(
+            "create_message",
+            self.inner
+                .create_message(channel_id, author_id, content, reply_to),
+        )
+        .await
+    }
+
+    async fn find_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<Option<Message>, StorageError> {
+        self.timed(
+            "find_message",
+            self.inner.find_message(channel_id, message_id),
+        )
+        .await
+    }
+
+    async fn edit_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        content: &str,
+    ) -> Result<Option<Message>, StorageError> {
+        self.timed(
+            "edit_message",
+            self.inner.edit_message(channel_id, message_id, content),
+        )
+        .await
+    }
+
+    async fn delete_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<bool, StorageError> {
+        self.timed(
+            "delete_message",
+            self.inner.delete_message(channel_id, message_id),
+        )
+        .await
+    }
+
+    async fn set_pinned(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        pinned: bool,
+    ) -> Result<Option<Message>, StorageError> {
+        self.timed(
+            "set_pinned",
+            self.inner.set_pinned(channel_id, message_id, pinned),
+        )
+        .await
+    }
+
+    async fn add_reaction(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        user_id: Snowflake,
+        emoji: &str,
+    ) -> Result<Option<bool>, StorageError> {
+        self.timed(
+            "add_reaction",
+            self.inner
+                .add_reaction(channel_id, message_id, user_id, emoji),
+        )
+        .await
+    }
+
+    async fn message_revisions(
+        &self,
+        message_id: Snowflake,
+    ) -> Result<Vec<MessageRevision>, StorageError> {
+        self.timed(
+            "message_revisions",
+            self.inner.message_revisions(message_id),
+        )
+        .await
+    }
+
+    async fn fetch_messages(
+        &self,
+        channel_id: Snowflake,
+        cursor: MessageCursor,
+        limit: u32,
+    ) -> Result<Vec<Message>, StorageError> {
+        self.timed(
+            "fetch_messages",
+            self.inner.fetch_messages(channel_id, cursor, limit),
+        )
+        .await
+    }
+}
@@ -0,0 +1,228 @@
+mod ids;
+mod memory;
+mod metered;
+mod postgres;
+
+use std::{error::Error, fmt::Display, sync::Arc};
+
+use async_trait::async_trait;
+use common::{
+    Guild, Invite, InvitePreview, Message, MessageCursor, MessageRevision, ReadState, Snowflake,
+    User,
+};
+
+use crate::config::DatabaseConfig;
+
+pub use ids::IdGenerator;
+pub use memory::MemoryStorage;
+pub(crate) use metered::MeteredStorage;
+pub use postgres::PostgresStorage;
+
+/// Everything the server persists. Implemented by [`PostgresStorage`] for real deployments and
+/// by [`MemoryStorage`] for tests and demos that should not need any external service.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Checks that the backend is reachable.
+    async fn ping(&self) -> Result<(), StorageError>;
+
+    /// Usage of the connection pool, for backends that have one.
+    fn pool_status(&self) -> Option<PoolStatus> {
+        None
+    }
+
+    /// Fails with [`StorageError::Conflict`] if the username is taken, ignoring case.
+    async fn create_user(&self, username: &str, password_hash: &str) -> Result<User, StorageError>;
+
+    /// Looks up a user by name, returning it together with its password hash.
+    async fn find_login(&self, username: &str) -> Result<Option<(User, String)>, StorageError>;
+
+    async fn create_session(
+        &self,
+        token_hash: &str,
+        user_id: Snowflake,
+        ttl_days: i32,
+    ) -> Result<(), StorageError>;
+
+    /// Returns the user of an unexpired session.
+    async fn find_session_user(&self, token_hash: &str) -> Result<Option<User>, StorageError>;
+
+    /// Creates a guild owned by `owner_id` with a single `general` text channel.
+    async fn create_guild(
+        &self,
+        owner_id: Snowflake,
+        name: &str,
+        icon_url: &str,
+    ) -> Result<Snowflake, StorageError>;
+
+    /// Returns the owner of a guild, `None` if the guild or its owner is gone.
+    async fn guild_owner(&self, guild_id: Snowflake) -> Result<Option<Snowflake>, StorageError>;
+
+    /// Loads a guild with its channels and members, but without any messages.
+    async fn load_guild(&self, guild_id: Snowflake) -> Result<Option<Guild>, StorageError>;
+
+    async fn read_states(&self, user_id: Snowflake) -> Result<Vec<ReadState>, StorageError>;
+
+    /// Moves the user's read state in a channel forward to `message_id`, returning the resulting
+    /// state, or `None` if the message is not in that channel.
+    async fn mark_read(
+        &self,
+        user_id: Snowflake,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<Option<ReadState>, StorageError>;
+
+    async fn is_member(
+        &self,
+        guild_id: Snowflake,
+        user_id: Snowflake,
+    ) -> Result<bool, StorageError>;
+
+    async fn create_invite(
+        &self,
+        code: &str,
+        guild_id: Snowflake,
+        creator_id: Snowflake,
+        max_age_secs: Option<u32>,
+        max_uses: Option<u32>,
+    ) -> Result<Invite, StorageError>;
+
+    /// Revokes an invite if `user_id` created it or owns its guild. Returns whether it did.
+    async fn revoke_invite(&self, code: &str, user_id: Snowflake) -> Result<bool, StorageError>;
+
+    /// Describes the guild of a usable invite.
+    async fn invite_preview(&self, code: &str) -> Result<Option<InvitePreview>, StorageError>;
+
+    /// Adds `user_id` to the invite's guild. Returns the guild id and whether the user was
+    /// newly added, or `None` if the invite is unknown, revoked, expired or used up.
+    async fn use_invite(
+        &self,
+        code: &str,
+        user_id: Snowflake,
+    ) -> Result<Option<(Snowflake, bool)>, StorageError>;
+
+    async fn guild_ids_for_user(&self, user_id: Snowflake) -> Result<Vec<Snowflake>, StorageError>;
+
+    /// Returns the guild of a text channel, provided `user_id` is a member of it.
+    async fn text_channel_guild(
+        &self,
+        channel_id: Snowflake,
+        user_id: Snowflake,
+    ) -> Result<Option<Snowflake>, StorageError>;
+
+    async fn create_message(
+        &self,
+        channel_id: Snowflake,
+        author_id: Snowflake,
+        content: &str,
+        reply_to: Option<Snowflake>,
+    ) -> Result<Message, StorageError>;
+
+    async fn find_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<Option<Message>, StorageError>;
+
+    /// Replaces the content of a message, keeping the previous content as a revision. Returns
+    /// the edited message, or `None` if the message is not in that channel.
+    async fn edit_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        content: &str,
+    ) -> Result<Option<Message>, StorageError>;
+
+    /// Deletes a message along with its revisions. Returns whether it was in that channel.
+    async fn delete_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<bool, StorageError>;
+
+    /// Returns the updated message, or `None` if the message is not in that channel.
+    async fn set_pinned(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        pinned: bool,
+    ) -> Result<Option<Message>, StorageError>;
+
+    /// Returns whether the user had not reacted with `emoji` yet, or `None` if the message is
+    /// not in that channel.
+    async fn add_reaction(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        user_id: Snowflake,
+        emoji: &str,
+    ) -> Result<Option<bool>, StorageError>;
+
+    /// The contents a message had before each of its edits, oldest first.
+    async fn message_revisions(
+        &self,
+        message_id: Snowflake,
+    ) -> Result<Vec<MessageRevision>, StorageError>;
+
+    /// Loads a page of a channel's history, oldest message first.
+    async fn fetch_messages(
+        &self,
+        channel_id: Snowflake,
+        cursor: MessageCursor,
+        limit: u32,
+    ) -> Result<Vec<Message>, StorageError>;
+}
+
+/// How busy a connection pool is.
+pub struct PoolStatus {
+    pub size: u32,
+    pub idle: u32,
+    pub max: u32,
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// A uniqueness constraint was violated, e.g. by a username that is already taken.
+    Conflict,
+    Backend(Box<dyn Error + Send + Sync>),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Conflict => write!(f, "conflicts with an existing record"),
+            StorageError::Backend(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(err: sqlx::Error) -> Self {
+        if err
+            .as_database_error()
+            .is_some_and(|err| err.is_unique_violation())
+        {
+            return StorageError::Conflict;
+        }
+        StorageError::Backend(Box::new(err))
+    }
+}
+
+/// Opens the storage selected by the database url: `memory:` keeps everything in this process and
+/// loses it on exit, anything else is taken as a Postgres connection string and migrated on
+/// startup. New ids are generated as `worker_id`.
+pub async fn open(
+    config: &DatabaseConfig,
+    worker_id: u16,
+) -> Result<Arc<dyn Storage>, Box<dyn Error>> {
+    let ids = IdGenerator::new(worker_id);
+    if config.url.starts_with("memory:") {
+        tracing::warn!("Using in-memory storage, nothing will be persisted");
+        return Ok(Arc::new(MemoryStorage::new(ids)));
+    }
+
+    let storage = PostgresStorage::connect(&config.url, config.max_connections, ids).await?;
+    storage.run_migrations().await?;
+    Ok(Arc::new(storage))
+}
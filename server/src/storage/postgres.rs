@@ -0,0 +1,719 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use common::{
+    Channel, ChannelKind, Guild, GuildMember, Invite, InvitePreview, Message, MessageCursor,
+    MessageRevision, ReadState, Snowflake, TextChannel, User, snowflake,
+};
+use sqlx::{
+    Pool, Postgres,
+    migrate::MigrateError,
+    postgres::{PgConnectOptions, PgPoolOptions},
+};
+
+use super::{IdGenerator, PoolStatus, Storage, StorageError};
+
+pub struct PostgresStorage {
+    pool: Pool<Postgres>,
+    ids: IdGenerator,
+}
+
+type UserRow = (i64, String, String);
+
+fn user_from_row((id, name, avatar_url): UserRow) -> User {
+    User {
+        id: id as Snowflake,
+        name,
+        avatar_url,
+    }
+}
+
+type MessageRow = (i64, i64, String, i64, Option<i64>, Option<i64>, bool);
+
+/// Reactions are stored separately, see [`PostgresStorage::load_reactions`].
+fn message_from_row(
+    (id, author_id, content, created_at, edited_at, reply_to, pinned): MessageRow,
+) -> Message {
+    Message {
+        id: id as Snowflake,
+        author_id: author_id as Snowflake,
+        content,
+        created_at,
+        edited_at,
+        reply_to: reply_to.map(|id| id as Snowflake),
+        pinned,
+        reactions: Vec::new(),
+    }
+}
+
+/// The columns of `messages` that make up a [`MessageRow`].
+const MESSAGE_COLUMNS: &str = "id, author_id, content,
+     (EXTRACT(EPOCH FROM created_at) * 1000)::BIGINT,
+     (EXTRACT(EPOCH FROM edited_at) * 1000)::BIGINT,
+     reply_to, pinned";
+
+impl PostgresStorage {
+    pub async fn connect(
+        url: &str,
+        max_connections: u32,
+        ids: IdGenerator,
+    ) -> Result<Self, sqlx::Error> {
+        Self::connect_with(url.parse()?, max_connections, ids).await
+    }
+
+    pub async fn connect_with(
+        options: PgConnectOptions,
+        max_connections: u32,
+        ids: IdGenerator,
+    ) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await?;
+        Ok(Self { pool, ids })
+    }
+
+    pub async fn run_migrations(&self) -> Result<(), MigrateError> {
+        sqlx::migrate!("./migrations").run(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Loads the messages of a channel that match `filter`, which may refer to the limit as `$2`
+    /// and to `id` as `$3`.
+    async fn query_messages(
+        &self,
+        filter: &str,
+        channel_id: Snowflake,
+        limit: u32,
+        id: Option<Snowflake>,
+    ) -> Result<Vec<Message>, sqlx::Error> {
+        let sql = format!("SELECT {MESSAGE_COLUMNS} FROM messages WHERE channel_id = $1 {filter}");
+        let mut query = sqlx::query_as::<_, MessageRow>(&sql)
+            .bind(channel_id as i64)
+            .bind(limit as i64);
+        if let Some(id) = id {
+            query = query.bind(id as i64);
+        }
+        let rows = query.fetch_all(&self.pool).await?;
+        let mut messages: Vec<_> = rows.into_iter().map(message_from_row).collect();
+        self.load_reactions(&mut messages).await?;
+        Ok(messages)
+    }
+
+    /// Fills in the reactions of messages loaded from a [`MessageRow`].
+    async fn load_reactions(&self, messages: &mut [Message]) -> Result<(), sqlx::Error> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<i64> = messages.iter().map(|message| message.id as i64).collect();
+        let rows: Vec<(i64, String, i64)> = sqlx::query_as(
+            "SELECT message_id, emoji, user_id FROM message_reactions
+             WHERE message_id = ANY($1) ORDER BY created_at, user_id",
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        for (message_id, emoji, user_id) in rows {
+            if let Some(message) = messages
+                .iter_mut()
+                .find(|message| message.id == message_id as Snowflake)
+            {
+                message.add_reaction(&emoji, user_id as Snowflake);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads a message from a row along with its reactions.
+    async fn complete_message(&self, row: MessageRow) -> Result<Message, sqlx::Error> {
+        let mut message = message_from_row(row);
+        self.load_reactions(std::slice::from_mut(&mut message))
+            .await?;
+        Ok(message)
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn ping(&self) -> Result<(), StorageError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        Some(PoolStatus {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max: self.pool.options().get_max_connections(),
+        })
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str) -> Result<User, StorageError> {
+        let row: UserRow = sqlx::query_as(
+            "INSERT INTO users (id, username, password_hash) VALUES ($1, $2, $3)
+             RETURNING id, username, avatar_url",
+        )
+        .bind(self.ids.next() as i64)
+        .bind(username)
+        .bind(password_hash)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(user_from_row(row))
+    }
+
+    async fn find_login(&self, username: &str) -> Result<Option<(User, String)>, StorageError> {
+        let row: Option<(i64, String, String, String)> = sqlx::query_as(
+            "SELECT id, username, avatar_url, password_hash FROM users
+             WHERE lower(username) = lower($1)",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|(id, name, avatar_url, password_hash)| {
+            (user_from_row((id, name, avatar_url)), password_hash)
+        }))
+    }
+
+    async fn create_session(
+        &self,
+        token_hash: &str,
+        user_id: Snowflake,
+        ttl_days: i32,
+    ) -> Result<(), StorageError> {
+        sqlx::query(
+            "INSERT INTO sessions (token_hash, user_id, expires_at)
+             VALUES ($1, $2, now() + make_interval(days => $3))",
+        )
+        .bind(token_hash)
+        .bind(user_id as i64)
+        .bind(ttl_days)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_session_user(&self, token_hash: &str) -> Result<Option<User>, StorageError> {
+        let row: Option<UserRow> = sqlx::query_as(
+            "SELECT users.id, users.username, users.avatar_url FROM sessions
+             JOIN users ON users.id = sessions.user_id
+             WHERE sessions.token_hash = $1 AND sessions.expires_at > now()",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(user_from_row))
+    }
+
+    async fn create_guild(
+        &self,
+        owner_id: Snowflake,
+        name: &str,
+        icon_url: &str,
+    ) -> Result<Snowflake, StorageError> {
+        let id = self.ids.next();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("INSERT INTO guilds (id, name, icon_url, owner_id) VALUES ($1, $2, $3, $4)")
+            .bind(id as i64)
+            .bind(name)
+            .bind(icon_url)
+            .bind(owner_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO guild_members (guild_id, user_id) VALUES ($1, $2)")
+            .bind(id as i64)
+            .bind(owner_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO channels (id, guild_id, name, kind, position)
+             VALUES ($1, $2, 'general', 'text', 0)",
+        )
+        .bind(self.ids.next() as i64)
+        .bind(id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    async fn guild_owner(&self, guild_id: Snowflake) -> Result<Option<Snowflake>, StorageError> {
+        let owner_id: Option<Option<i64>> =
+            sqlx::query_scalar("SELECT owner_id FROM guilds WHERE id = $1")
+                .bind(guild_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(owner_id.flatten().map(|id| id as Snowflake))
+    }
+
+    async fn load_guild(&self, guild_id: Snowflake) -> Result<Option<Guild>, StorageError> {
+        let row: Option<(String, String, Option<i64>)> =
+            sqlx::query_as("SELECT name, icon_url, owner_id FROM guilds WHERE id = $1")
+                .bind(guild_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some((name, icon_url, owner_id)) = row else {
+            return Ok(None);
+        };
+
+        let channels: Vec<(i64, String, String, String, Option<i64>)> = sqlx::query_as(
+            "SELECT id, name, kind, description,
+                 (SELECT max(id) FROM messages WHERE messages.channel_id = channels.id)
+             FROM channels
+             WHERE guild_id = $1 ORDER BY position, id",
+        )
+        .bind(guild_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let channels = channels
+            .into_iter()
+            .map(|(id, name, kind, description, last_message_id)| Channel {
+                id: id as Snowflake,
+                name,
+                kind: match kind.as_str() {
+                    "voice" => ChannelKind::Voice,
+                    _ => ChannelKind::Text(TextChannel {
+                        messages: Vec::new(),
+                        last_message_id: last_message_id.map(|id| id as Snowflake),
+                    }),
+                },
+                description,
+            })
+            .collect();
+
+        let members: Vec<UserRow> = sqlx::query_as(
+            "SELECT users.id, users.username, users.avatar_url FROM guild_members
+             JOIN users ON users.id = guild_members.user_id
+             WHERE guild_members.guild_id = $1",
+        )
+        .bind(guild_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let members = members
+            .into_iter()
+            .map(|(id, name, avatar_url)| (id as Snowflake, GuildMember { name, avatar_url }))
+            .collect::<HashMap<_, _>>();
+
+        Ok(Some(Guild {
+            id: guild_id,
+            name,
+            icon_url,
+            owner_id: owner_id.map(|id| id as Snowflake),
+            channels,
+            members,
+        }))
+    }
+
+    async fn read_states(&self, user_id: Snowflake) -> Result<Vec<ReadState>, StorageError> {
+        let rows: Vec<(i64, i64)> =
+            sqlx::query_as("SELECT channel_id, last_read_id FROM read_states WHERE user_id = $1")
+                .bind(user_id as i64)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(channel_id, last_read_id)| ReadState {
+                channel_id: channel_id as Snowflake,
+                last_read_id: last_read_id as Snowflake,
+            })
+            .collect())
+    }
+
+    async fn mark_read(
+        &self,
+        user_id: Snowflake,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<Option<ReadState>, StorageError> {
+        let last_read_id: Option<i64> = sqlx::query_scalar(
+            "INSERT INTO read_states (user_id, channel_id, last_read_id)
+             SELECT $1, channel_id, id FROM messages WHERE id = $3 AND channel_id = $2
+             ON CONFLICT (user_id, channel_id) DO UPDATE
+                 SET last_read_id = GREATEST(read_states.last_read_id, EXCLUDED.last_read_id)
+             RETURNING last_read_id",
+        )
+        .bind(user_id as i64)
+        .bind(channel_id as i64)
+        .bind(message_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(last_read_id.map(|last_read_id| ReadState {
+            channel_id,
+            last_read_id: last_read_id as Snowflake,
+        }))
+    }
+
+    async fn is_member(
+        &self,
+        guild_id: Snowflake,
+        user_id: Snowflake,
+    ) -> Result<bool, StorageError> {
+        let is_member = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM guild_members WHERE guild_id = $1 AND user_id = $2)",
+        )
+        .bind(guild_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(is_member)
+    }
+
+    async fn create_invite(
+        &self,
+        code: &str,
+        guild_id: Snowflake,
+        creator_id: Snowflake,
+        max_age_secs: Option<u32>,
+        max_uses: Option<u32>,
+    ) -> Result<Invite, StorageError> {
+        let expires_at: Option<i64> = sqlx::query_scalar(
+            "INSERT INTO invites (code, guild_id, creator_id, expires_at, max_uses)
+             VALUES ($1, $2, $3, now() + make_interval(secs => $4), $5)
+             RETURNING EXTRACT(EPOCH FROM expires_at)::BIGINT",
+        )
+        .bind(code)
+        .bind(guild_id as i64)
+        .bind(creator_id as i64)
+        .bind(max_age_secs.map(f64::from))
+        .bind(max_uses.map(|uses| uses as i32))
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(Invite {
+            code: code.to_string(),
+            guild_id,
+            creator_id,
+            expires_at,
+            max_uses,
+            uses: 0,
+        })
+    }
+
+    async fn revoke_invite(&self, code: &str, user_id: Snowflake) -> Result<bool, StorageError> {
+        let result = sqlx::query(
+            "UPDATE invites SET revoked = true
+             FROM guilds
+             WHERE invites.code = $1 AND guilds.id = invites.guild_id
+               AND (invites.creator_id = $2 OR guilds.owner_id = $2)",
+        )
+        .bind(code)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn invite_preview(&self, code: &str) -> Result<Option<InvitePreview>, StorageError> {
+        let row: Option<(String, String, i64)> = sqlx::query_as(
+            "SELECT guilds.name, guilds.icon_url,
+                    (SELECT count(*) FROM guild_members WHERE guild_id = guilds.id)
+             FROM invites JOIN guilds ON guilds.id = invites.guild_id
+             WHERE invites.code = $1 AND NOT invites.revoked
+               AND (invites.expires_at IS NULL OR invites.expires_at > now())
+               AND (invites.max_uses IS NULL OR invites.uses < invites.max_uses)",
+        )
+        .bind(code)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(
+            row.map(|(guild_name, icon_url, member_count)| InvitePreview {
+                code: code.to_string(),
+                guild_name,
+                icon_url,
+                member_count: member_count as u32,
+            }),
+        )
+    }
+
+    async fn use_invite(
+        &self,
+        code: &str,
+        user_id: Snowflake,
+    ) -> Result<Option<(Snowflake, bool)>, StorageError> {
+        let mut tx = self.pool.begin().await?;
+        let guild_id: Option<i64> = sqlx::query_scalar(
+            "SELECT guild_id FROM invites
+             WHERE code = $1 AND NOT revoked
+               AND (expires_at IS NULL OR expires_at > now())
+               AND (max_uses IS NULL OR uses < max_uses)
+             FOR UPDATE",
+        )
+        .bind(code)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(guild_id) = guild_id else {
+            return Ok(None);
+        };
+
+        let joined = sqlx::query(
+            "INSERT INTO guild_members (guild_id, user_id) VALUES ($1, $2)
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(guild_id)
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        if joined {
+            sqlx::query("UPDATE invites SET uses = uses + 1 WHERE code = $1")
+                .bind(code)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(Some((guild_id as Snowflake, joined)))
+    }
+
+    async fn guild_ids_for_user(&self, user_id: Snowflake) -> Result<Vec<Snowflake>, StorageError> {
+        let ids: Vec<i64> =
+            sqlx::query_scalar("SELECT guild_id FROM guild_members WHERE user_id = $1")
+                .bind(user_id as i64)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(ids.into_iter().map(|id| id as Snowflake).collect())
+    }
+
+    async fn text_channel_guild(
+        &self,
+        channel_id: Snowflake,
+        user_id: Snowflake,
+    ) -> Result<Option<Snowflake>, StorageError> {
+        let guild_id: Option<i64> = sqlx::query_scalar(
+            "SELECT channels.guild_id FROM channels
+             JOIN guild_members ON guild_members.guild_id = channels.guild_id
+             WHERE channels.id = $1 AND channels.kind = 'text' AND guild_members.user_id = $2",
+        )
+        .bind(channel_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(guild_id.map(|id| id as Snowflake))
+    }
+
+    async fn create_message(
+        &self,
+        channel_id: Snowflake,
+        author_id: Snowflake,
+        content: &str,
+        reply_to: Option<Snowflake>,
+    ) -> Result<Message, StorageError> {
+        let id = self.ids.next();
+        let created_at = snowflake::timestamp_ms(id) as i64;
+        sqlx::query(
+            "INSERT INTO messages (id, channel_id, author_id, content, created_at, reply_to)
+             VALUES ($1, $2, $3, $4, to_timestamp($5 / 1000.0), $6)",
+        )
+        .bind(id as i64)
+        .bind(channel_id as i64)
+        .bind(author_id as i64)
+        .bind(content)
+        .bind(created_at)
+        .bind(reply_to.map(|id| id as i64))
+        .execute(&self.pool)
+        .await?;
+        Ok(Message {
+            id,
+            author_id,
+            content: content.to_string(),
+            created_at,
+            edited_at: None,
+            reply_to,
+            pinned: false,
+            reactions: Vec::new(),
+        })
+    }
+
+    async fn find_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<Option<Message>, StorageError> {
+        let row: Option<MessageRow> = sqlx::query_as(&format!(
+            "SELECT {MESSAGE_COLUMNS} FROM messages WHERE id = $1 AND channel_id = $2"
+        ))
+        .bind(message_id as i64)
+        .bind(channel_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        match row {
+            Some(row) => Ok(Some(self.complete_message(row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn edit_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        content: &str,
+    ) -> Result<Option<Message>, StorageError> {
+        let mut tx = self.pool.begin().await?;
+        let found: Option<i64> = sqlx::query_scalar(
+            "SELECT id FROM messages WHERE id = $1 AND channel_id = $2 FOR UPDATE",
+        )
+        .bind(message_id as i64)
+        .bind(channel_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if found.is_none() {
+            return Ok(None);
+        }
+        sqlx::query(
+            "INSERT INTO message_revisions (message_id, content, written_at)
+             SELECT id, content, COALESCE(edited_at, created_at) FROM messages WHERE id = $1",
+        )
+        .bind(message_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        let row: MessageRow = sqlx::query_as(&format!(
+            "UPDATE messages SET content = $2, edited_at = now() WHERE id = $1
+             RETURNING {MESSAGE_COLUMNS}"
+        ))
+        .bind(message_id as i64)
+        .bind(content)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some(self.complete_message(row).await?))
+    }
+
+    async fn delete_message(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+    ) -> Result<bool, StorageError> {
+        let result = sqlx::query("DELETE FROM messages WHERE id = $1 AND channel_id = $2")
+            .bind(message_id as i64)
+            .bind(channel_id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_pinned(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        pinned: bool,
+    ) -> Result<Option<Message>, StorageError> {
+        let row: Option<MessageRow> = sqlx::query_as(&format!(
+            "UPDATE messages SET pinned = $3 WHERE id = $1 AND channel_id = $2
+             RETURNING {MESSAGE_COLUMNS}"
+        ))
+        .bind(message_id as i64)
+        .bind(channel_id as i64)
+        .bind(pinned)
+        .fetch_optional(&self.pool)
+        .await?;
+        match row {
+            Some(row) => Ok(Some(self.complete_message(row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn add_reaction(
+        &self,
+        channel_id: Snowflake,
+        message_id: Snowflake,
+        user_id: Snowflake,
+        emoji: &str,
+    ) -> Result<Option<bool>, StorageError> {
+        let added = sqlx::query(
+            "INSERT INTO message_reactions (message_id, user_id, emoji)
+             SELECT id, $3, $4 FROM messages WHERE id = $1 AND channel_id = $2
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(message_id as i64)
+        .bind(channel_id as i64)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if added {
+            return Ok(Some(true));
+        }
+        // Nothing was inserted, either because the reaction exists or the message doesn't.
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM messages WHERE id = $1 AND channel_id = $2)",
+        )
+        .bind(message_id as i64)
+        .bind(channel_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists.then_some(false))
+    }
+
+    async fn message_revisions(
+        &self,
+        message_id: Snowflake,
+    ) -> Result<Vec<MessageRevision>, StorageError> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT content, (EXTRACT(EPOCH FROM written_at) * 1000)::BIGINT
+             FROM message_revisions WHERE message_id = $1 ORDER BY written_at",
+        )
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(content, written_at)| MessageRevision {
+                content,
+                written_at,
+            })
+            .collect())
+    }
+
+    async fn fetch_messages(
+        &self,
+        channel_id: Snowflake,
+        cursor: MessageCursor,
+        limit: u32,
+    ) -> Result<Vec<Message>, StorageError> {
+        let (newest_first, oldest_first) = match cursor {
+            MessageCursor::Latest => (
+                self.query_messages("ORDER BY id DESC LIMIT $2", channel_id, limit, None)
+                    .await?,
+                Vec::new(),
+            ),
+            MessageCursor::Before(id) => (
+                self.query_messages(
+                    "AND id < $3 ORDER BY id DESC LIMIT $2",
+                    channel_id,
+                    limit,
+                    Some(id),
+                )
+                .await?,
+                Vec::new(),
+            ),
+            MessageCursor::After(id) => (
+                Vec::new(),
+                self.query_messages(
+                    "AND id > $3 ORDER BY id ASC LIMIT $2",
+                    channel_id,
+                    limit,
+                    Some(id),
+                )
+                .await?,
+            ),
+            MessageCursor::Around(id) => (
+                self.query_messages(
+                    "AND id <= $3 ORDER BY id DESC LIMIT $2",
+                    channel_id,
+                    limit.div_ceil(2),
+                    Some(id),
+                )
+                .await?,
+                self.query_messages(
+                    "AND id > $3 ORDER BY id ASC LIMIT $2",
+                    channel_id,
+                    limit / 2,
+                    Some(id),
+                )
+                .await?,
+            ),
+        };
+
+        Ok(newest_first.into_iter().rev().chain(oldest_first).collect())
+    }
+}
@@ -0,0 +1,117 @@
+use std::{collections::HashMap, ffi::OsString, fs, net::SocketAddr};
+
+use server::config::{Config, ConfigError};
+
+fn load(file: Option<&str>, env: &[(&str, &str)], args: &[&str]) -> Result<Config, ConfigError> {
+    let env: HashMap<String, String> = env
+        .iter()
+        .map(|&(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    let mut args: Vec<OsString> = args.iter().map(OsString::from).collect();
+    let path = std::env::temp_dir().join(format!("server-config-{}.toml", rand::random::<u64>()));
+    if let Some(file) = file {
+        fs::write(&path, file).unwrap();
+        args.extend(["--config".into(), path.clone().into()]);
+    }
+    let config = Config::from_sources(|name| env.get(name).cloned(), args);
+    let _ = fs::remove_file(path);
+    config
+}
+
+#[test]
+fn later_layers_override_earlier_ones() {
+    let file = r#"
+        bind = "127.0.0.1:4000"
+        [database]
+        url = "memory:"
+        max_connections = 8
+        [heartbeat]
+        interval_secs = 10
+    "#;
+    let config = load(
+        Some(file),
+        &[
+            ("DATABASE_MAX_CONNECTIONS", "12"),
+            ("HEARTBEAT_MAX_MISSED", "5"),
+        ],
+        &["--max-connections", "20"],
+    )
+    .unwrap();
+
+    assert_eq!(config.bind, "127.0.0.1:4000".parse::<SocketAddr>().unwrap());
+    assert_eq!(config.database.url, "memory:");
+    assert_eq!(config.database.max_connections, 20);
+    assert_eq!(config.heartbeat.interval_secs, 10);
+    assert_eq!(config.heartbeat.max_missed, 5);
+    assert_eq!(config.limits.max_message_len, 4000);
+}
+
+#[test]
+fn invalid_values_name_their_source() {
+    let err = load(
+        None,
+        &[
+            ("DATABASE_URL", "memory:"),
+            ("HEARTBEAT_INTERVAL_SECS", "soon"),
+        ],
+        &[],
+    )
+    .unwrap_err();
+    assert!(
+        matches!(&err, ConfigError::Invalid { key, .. } if key == "HEARTBEAT_INTERVAL_SECS"),
+        "{}",
+        err
+    );
+
+    let err = load(
+        None,
+        &[],
+        &[
+            "--database-url",
+            "memory:",
+            "--log-filter",
+            "info,server=loud",
+        ],
+    )
+    .unwrap_err();
+    assert!(
+        matches!(&err, ConfigError::Invalid { key, .. } if key == "--log-filter"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn database_url_is_required() {
+    let err = load(None, &[], &[]).unwrap_err();
+    assert!(
+        matches!(&err, ConfigError::Invalid { key, .. } if key == "database.url"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn heartbeat_interval_must_fit_the_wire_format() {
+    let env = [("DATABASE_URL", "memory:")];
+    assert!(load(None, &env, &["--heartbeat-interval", "4294967"]).is_ok());
+    let err = load(None, &env, &["--heartbeat-interval", "4294968"]).unwrap_err();
+    assert!(
+        matches!(&err, ConfigError::Invalid { key, .. } if key == "heartbeat.interval_secs"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn unknown_keys_and_flags_are_rejected() {
+    let err = load(Some("[database]\nurl = \"memory:\"\npool = 3\n"), &[], &[]).unwrap_err();
+    assert!(matches!(err, ConfigError::Parse { .. }), "{}", err);
+
+    let err = load(None, &[("DATABASE_URL", "memory:")], &["--verbose"]).unwrap_err();
+    assert!(
+        matches!(&err, ConfigError::Invalid { key, .. } if key == "--verbose"),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,670 @@
+mod harness;
+
+use std::time::{Duration, SystemTime};
+
+use common::{
+    AddReaction, AuthResponse, ChannelKind, ClientMessage, CreateGuild, CreateInvite,
+    DeleteMessage, EditMessage, ErrorCode, FetchMessageRevisions, FetchMessages, Hello,
+    InvitePreview, JoinGuild, JoinGuildByCode, MemberJoin, MessageCreate, MessageCursor,
+    MessageDelete, MessageRevisions, MessageUpdate, Messages, PinMessage, PreviewInvite,
+    ReactionAdd, Request, Resumed, SendMessage, ServerMessage, Snowflake, snowflake,
+};
+use harness::{TestClient, TestServer};
+use server::config::{Config, RateLimit};
+
+/// Creates a guild as `owner` and returns it from the resulting `JoinGuild`.
+async fn create_guild(owner: &mut TestClient, name: &str) -> common::Guild {
+    let replies = owner
+        .request_ok(Request::CreateGuild(CreateGuild {
+            name: name.to_string(),
+            icon_url: String::new(),
+        }))
+        .await;
+    replies
+        .into_iter()
+        .find_map(|msg| match msg {
+            ServerMessage::JoinGuild(JoinGuild { guild }) => Some(guild),
+            _ => None,
+        })
+        .expect("no JoinGuild for the new guild")
+}
+
+async fn create_invite(member: &mut TestClient, guild_id: Snowflake) -> String {
+    let replies = member
+        .request_ok(Request::CreateInvite(CreateInvite {
+            guild_id,
+            max_age_secs: Some(60),
+            max_uses: None,
+        }))
+        .await;
+    replies
+        .into_iter()
+        .find_map(|msg| match msg {
+            ServerMessage::InviteCreated(invite) => Some(invite.code),
+            _ => None,
+        })
+        .expect("no InviteCreated")
+}
+
+/// Posts `content` and returns the message from the resulting `MessageCreate`.
+async fn send_message(
+    author: &mut TestClient,
+    channel_id: Snowflake,
+    content: &str,
+) -> common::Message {
+    let replies = author
+        .request_ok(Request::SendMessage(SendMessage {
+            channel_id,
+            content: content.to_string(),
+            nonce: 0,
+            reply_to: None,
+        }))
+        .await;
+    replies
+        .into_iter()
+        .find_map(|msg| match msg {
+            ServerMessage::MessageCreate(MessageCreate { message, .. }) => Some(message),
+            _ => None,
+        })
+        .expect("no MessageCreate for the new message")
+}
+
+fn text_channel_id(guild: &common::Guild) -> Snowflake {
+    guild
+        .channels
+        .iter()
+        .find(|channel| matches!(channel.kind, ChannelKind::Text(_)))
+        .expect("guild has no text channel")
+        .id
+}
+
+#[tokio::test]
+async fn invited_member_receives_messages() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+
+    let guild = create_guild(&mut alice, "rustaceans").await;
+    let channel_id = text_channel_id(&guild);
+    let code = create_invite(&mut alice, guild.id).await;
+
+    let replies = bob
+        .request_ok(Request::PreviewInvite(PreviewInvite { code: code.clone() }))
+        .await;
+    assert!(replies.iter().any(|msg| matches!(
+        msg,
+        ServerMessage::InvitePreview(InvitePreview { guild_name, member_count: 1, .. })
+            if guild_name == "rustaceans"
+    )));
+
+    let replies = bob
+        .request_ok(Request::JoinGuild(JoinGuildByCode { code }))
+        .await;
+    let joined = replies
+        .into_iter()
+        .find_map(|msg| match msg {
+            ServerMessage::JoinGuild(JoinGuild { guild }) => Some(guild),
+            _ => None,
+        })
+        .expect("bob got no JoinGuild");
+    assert_eq!(joined.id, guild.id);
+    assert_eq!(joined.members.len(), 2);
+
+    let bob_id = bob.user().id;
+    let member_join = alice
+        .expect(|msg| match msg {
+            ServerMessage::MemberJoin(member_join) => Some(member_join),
+            _ => None,
+        })
+        .await;
+    assert!(matches!(member_join, MemberJoin { guild_id, user_id, .. }
+        if guild_id == guild.id && user_id == bob_id));
+
+    bob.request_ok(Request::SendMessage(SendMessage {
+        channel_id,
+        content: "hello alice".to_string(),
+        nonce: 7,
+        reply_to: None,
+    }))
+    .await;
+    let received = alice
+        .expect(|msg| match msg {
+            ServerMessage::MessageCreate(message_create) => Some(message_create),
+            _ => None,
+        })
+        .await;
+    assert_eq!(received.channel_id, channel_id);
+    assert_eq!(received.message.author_id, bob_id);
+    assert_eq!(received.message.content, "hello alice");
+}
+
+#[tokio::test]
+async fn sender_gets_its_nonce_back_before_the_ack() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let guild = create_guild(&mut alice, "nonces").await;
+
+    let replies = alice
+        .request_ok(Request::SendMessage(SendMessage {
+            channel_id: text_channel_id(&guild),
+            content: "pending no more".to_string(),
+            nonce: 42,
+            reply_to: None,
+        }))
+        .await;
+    assert!(matches!(
+        replies.as_slice(),
+        [
+            ServerMessage::MessageCreate(MessageCreate {
+                nonce: Some(42),
+                ..
+            }),
+            ServerMessage::Ack(_)
+        ]
+    ));
+}
+
+#[tokio::test]
+async fn history_pages_are_oldest_first() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let guild = create_guild(&mut alice, "history").await;
+    let channel_id = text_channel_id(&guild);
+
+    for i in 0..5 {
+        alice
+            .request_ok(Request::SendMessage(SendMessage {
+                channel_id,
+                content: format!("message {}", i),
+                nonce: i,
+                reply_to: None,
+            }))
+            .await;
+    }
+
+    let replies = alice
+        .request_ok(Request::FetchMessages(FetchMessages {
+            channel_id,
+            cursor: MessageCursor::Latest,
+            limit: 3,
+        }))
+        .await;
+    let Some(ServerMessage::Messages(Messages { messages, .. })) = replies.first() else {
+        panic!("expected Messages, got {:?}", replies);
+    };
+    let contents: Vec<_> = messages.iter().map(|msg| msg.content.as_str()).collect();
+    assert_eq!(contents, ["message 2", "message 3", "message 4"]);
+}
+
+#[tokio::test]
+async fn message_ids_carry_their_creation_time() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let guild = create_guild(&mut alice, "snowflakes").await;
+    let channel_id = text_channel_id(&guild);
+
+    let first = send_message(&mut alice, channel_id, "first").await;
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let between = SystemTime::now();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let second = send_message(&mut alice, channel_id, "second").await;
+
+    assert!(first.id < second.id);
+    assert_eq!(first.created_at as u64, snowflake::timestamp_ms(first.id));
+    assert_eq!(first.edited_at, None);
+    assert!(snowflake::created_at(first.id) <= between);
+    assert!(snowflake::created_at(second.id) >= between);
+
+    // A point in time works as a cursor without knowing any message id.
+    let replies = alice
+        .request_ok(Request::FetchMessages(FetchMessages {
+            channel_id,
+            cursor: MessageCursor::After(snowflake::at(between)),
+            limit: 10,
+        }))
+        .await;
+    let Some(ServerMessage::Messages(Messages { messages, .. })) = replies.first() else {
+        panic!("expected Messages, got {:?}", replies);
+    };
+    let contents: Vec<_> = messages.iter().map(|msg| msg.content.as_str()).collect();
+    assert_eq!(contents, ["second"]);
+}
+
+#[tokio::test]
+async fn non_members_cannot_post() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut mallory = server.connect("mallory").await;
+    let guild = create_guild(&mut alice, "private").await;
+
+    let replies = mallory
+        .request(Request::SendMessage(SendMessage {
+            channel_id: text_channel_id(&guild),
+            content: "let me in".to_string(),
+            nonce: 1,
+            reply_to: None,
+        }))
+        .await;
+    assert!(matches!(
+        replies.last(),
+        Some(ServerMessage::Error(err)) if err.code == ErrorCode::NotFound
+    ));
+}
+
+fn is_error(replies: &[ServerMessage], code: ErrorCode) -> bool {
+    matches!(replies.last(), Some(ServerMessage::Error(err)) if err.code == code)
+}
+
+#[tokio::test]
+async fn authors_edit_and_moderators_review_and_delete() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    let guild = create_guild(&mut alice, "moderated").await;
+    let channel_id = text_channel_id(&guild);
+    let code = create_invite(&mut alice, guild.id).await;
+    bob.request_ok(Request::JoinGuild(JoinGuildByCode { code }))
+        .await;
+
+    let message = send_message(&mut bob, channel_id, "frist").await;
+    assert!(guild.is_moderator(alice.user().id));
+
+    // Only the author may edit, even the owner may not.
+    let replies = alice
+        .request(Request::EditMessage(EditMessage {
+            channel_id,
+            message_id: message.id,
+            content: "owned".to_string(),
+        }))
+        .await;
+    assert!(is_error(&replies, ErrorCode::NotPermitted));
+
+    bob.request_ok(Request::EditMessage(EditMessage {
+        channel_id,
+        message_id: message.id,
+        content: "first".to_string(),
+    }))
+    .await;
+    let edited = alice
+        .expect(|msg| match msg {
+            ServerMessage::MessageUpdate(MessageUpdate { message, .. }) => Some(message),
+            _ => None,
+        })
+        .await;
+    assert_eq!(edited.id, message.id);
+    assert_eq!(edited.content, "first");
+    assert_eq!(edited.created_at, message.created_at);
+    assert!(edited.edited_at.is_some());
+
+    // Earlier versions are for moderators only.
+    let fetch = Request::FetchMessageRevisions(FetchMessageRevisions {
+        channel_id,
+        message_id: message.id,
+    });
+    let replies = bob.request(fetch.clone()).await;
+    assert!(is_error(&replies, ErrorCode::NotPermitted));
+    let replies = alice.request_ok(fetch).await;
+    let Some(ServerMessage::MessageRevisions(MessageRevisions { revisions, .. })) = replies.first()
+    else {
+        panic!("expected MessageRevisions, got {:?}", replies);
+    };
+    let contents: Vec<_> = revisions.iter().map(|rev| rev.content.as_str()).collect();
+    assert_eq!(contents, ["frist"]);
+
+    // Moderators may delete anyone's message.
+    alice
+        .request_ok(Request::DeleteMessage(DeleteMessage {
+            channel_id,
+            message_id: message.id,
+        }))
+        .await;
+    let deleted = bob
+        .expect(|msg| match msg {
+            ServerMessage::MessageDelete(MessageDelete { message_id, .. }) => Some(message_id),
+            _ => None,
+        })
+        .await;
+    assert_eq!(deleted, message.id);
+
+    let replies = bob
+        .request(Request::EditMessage(EditMessage {
+            channel_id,
+            message_id: message.id,
+            content: "too late".to_string(),
+        }))
+        .await;
+    assert!(is_error(&replies, ErrorCode::NotFound));
+}
+
+#[tokio::test]
+async fn members_cannot_delete_others_messages() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    let guild = create_guild(&mut alice, "moderated").await;
+    let channel_id = text_channel_id(&guild);
+    let code = create_invite(&mut alice, guild.id).await;
+    bob.request_ok(Request::JoinGuild(JoinGuildByCode { code }))
+        .await;
+
+    let message = send_message(&mut alice, channel_id, "owner's words").await;
+    let replies = bob
+        .request(Request::DeleteMessage(DeleteMessage {
+            channel_id,
+            message_id: message.id,
+        }))
+        .await;
+    assert!(is_error(&replies, ErrorCode::NotPermitted));
+
+    // Authors may delete their own messages.
+    let own = send_message(&mut bob, channel_id, "never mind").await;
+    bob.request_ok(Request::DeleteMessage(DeleteMessage {
+        channel_id,
+        message_id: own.id,
+    }))
+    .await;
+    let replies = alice
+        .request_ok(Request::FetchMessages(FetchMessages {
+            channel_id,
+            cursor: MessageCursor::Latest,
+            limit: 10,
+        }))
+        .await;
+    let messages = replies
+        .into_iter()
+        .find_map(|msg| match msg {
+            ServerMessage::Messages(Messages { messages, .. }) => Some(messages),
+            _ => None,
+        })
+        .expect("no Messages");
+    let ids: Vec<_> = messages.iter().map(|msg| msg.id).collect();
+    assert_eq!(ids, [message.id]);
+}
+
+#[tokio::test]
+async fn members_reply_and_react_while_moderators_pin() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    let guild = create_guild(&mut alice, "threads").await;
+    let channel_id = text_channel_id(&guild);
+    let code = create_invite(&mut alice, guild.id).await;
+    bob.request_ok(Request::JoinGuild(JoinGuildByCode { code }))
+        .await;
+    let question = send_message(&mut alice, channel_id, "lunch?").await;
+    let reply = |reply_to| {
+        Request::SendMessage(SendMessage {
+            channel_id,
+            content: "sure".to_string(),
+            nonce: 0,
+            reply_to: Some(reply_to),
+        })
+    };
+
+    bob.request_ok(reply(question.id)).await;
+    let answer = alice
+        .expect(|msg| match msg {
+            ServerMessage::MessageCreate(MessageCreate { message, .. }) => Some(message),
+            _ => None,
+        })
+        .await;
+    assert_eq!(answer.reply_to, Some(question.id));
+    let replies = bob.request(reply(question.id + 1)).await;
+    assert!(is_error(&replies, ErrorCode::NotFound));
+
+    let pin = Request::PinMessage(PinMessage {
+        channel_id,
+        message_id: answer.id,
+        pinned: true,
+    });
+    let replies = bob.request(pin.clone()).await;
+    assert!(is_error(&replies, ErrorCode::NotPermitted));
+    alice.request_ok(pin).await;
+    let pinned = bob
+        .expect(|msg| match msg {
+            ServerMessage::MessageUpdate(MessageUpdate { message, .. }) => Some(message),
+            _ => None,
+        })
+        .await;
+    assert!(pinned.pinned);
+    assert_eq!(pinned.edited_at, None);
+
+    let react = |emoji: &str| {
+        Request::AddReaction(AddReaction {
+            channel_id,
+            message_id: question.id,
+            emoji: emoji.to_string(),
+        })
+    };
+    let replies = bob.request(react("not an emoji")).await;
+    assert!(is_error(&replies, ErrorCode::InvalidRequest));
+    bob.request_ok(react("👍")).await;
+    bob.request_ok(react("👍")).await;
+    // Reactions reach everyone in the guild, the reacting user included.
+    let alice_id = alice.user().id;
+    let replies = alice.request_ok(react("👍")).await;
+    assert!(replies.iter().any(|msg| matches!(
+        msg,
+        ServerMessage::ReactionAdd(ReactionAdd { message_id, user_id, emoji, .. })
+            if *message_id == question.id && *user_id == alice_id && emoji == "👍"
+    )));
+
+    let replies = alice
+        .request_ok(Request::FetchMessages(FetchMessages {
+            channel_id,
+            cursor: MessageCursor::Latest,
+            limit: 10,
+        }))
+        .await;
+    let messages = replies
+        .into_iter()
+        .find_map(|msg| match msg {
+            ServerMessage::Messages(Messages { messages, .. }) => Some(messages),
+            _ => None,
+        })
+        .expect("no Messages");
+    let [question, answer] = &messages[..] else {
+        panic!("expected two messages, got {:?}", messages);
+    };
+    assert_eq!(question.reactions.len(), 1);
+    assert_eq!(question.reactions[0].user_ids, [bob.user().id, alice_id]);
+    assert!(answer.pinned);
+    assert_eq!(answer.reply_to, Some(question.id));
+}
+
+#[tokio::test]
+async fn usernames_are_unique_ignoring_case() {
+    let server = TestServer::start().await;
+    assert!(matches!(
+        server.register("alice").await,
+        AuthResponse::Ok { .. }
+    ));
+    assert!(matches!(
+        server.register("Alice").await,
+        AuthResponse::Error {
+            code: ErrorCode::NameTaken,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn mismatched_protocol_version_is_refused() {
+    let server = TestServer::start().await;
+    let AuthResponse::Ok { token, .. } = server.register("alice").await else {
+        panic!("failed to register");
+    };
+    let mut client = server.open(&token).await;
+    client
+        .send(ClientMessage::Hello(Hello {
+            protocol_version: 0,
+            client_name: "ancient".to_string(),
+            capabilities: Vec::new(),
+        }))
+        .await;
+    assert!(matches!(client.recv().await, ServerMessage::Refused(_)));
+}
+
+#[tokio::test]
+async fn resumed_session_replays_missed_messages() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    let guild = create_guild(&mut alice, "flaky wifi").await;
+    let code = create_invite(&mut alice, guild.id).await;
+    bob.request_ok(Request::JoinGuild(JoinGuildByCode { code }))
+        .await;
+
+    let token = bob.token.clone();
+    let (session_id, last_seq) = (bob.session_id.unwrap(), bob.last_seq);
+    bob.close().await;
+
+    alice
+        .request_ok(Request::SendMessage(SendMessage {
+            channel_id: text_channel_id(&guild),
+            content: "you missed this".to_string(),
+            nonce: 1,
+            reply_to: None,
+        }))
+        .await;
+
+    let mut bob = server.open(&token).await;
+    bob.resume(session_id, last_seq).await;
+    assert!(matches!(
+        bob.recv().await,
+        ServerMessage::Resumed(Resumed { session_id: resumed }) if resumed == session_id
+    ));
+    let missed = bob
+        .expect(|msg| match msg {
+            ServerMessage::MessageCreate(message_create) => Some(message_create),
+            _ => None,
+        })
+        .await;
+    assert_eq!(missed.message.content, "you missed this");
+}
+
+#[tokio::test]
+async fn unknown_session_cannot_be_resumed() {
+    let server = TestServer::start().await;
+    let AuthResponse::Ok { token, .. } = server.register("alice").await else {
+        panic!("failed to register");
+    };
+    let mut client = server.open(&token).await;
+    client.resume(12345, 0).await;
+    assert!(matches!(client.recv().await, ServerMessage::InvalidSession));
+
+    // The socket stays usable for a fresh handshake.
+    client.hello().await;
+    assert_eq!(client.user().name, "alice");
+}
+
+#[tokio::test]
+async fn metrics_count_sessions_and_messages() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+    let guild = create_guild(&mut alice, "metrics").await;
+    alice
+        .request_ok(Request::SendMessage(SendMessage {
+            channel_id: text_channel_id(&guild),
+            content: "counted".to_string(),
+            nonce: 1,
+            reply_to: None,
+        }))
+        .await;
+
+    let (status, body) = server.get("/metrics").await;
+    assert_eq!(status, 200);
+    for line in [
+        "chat_sessions{state=\"attached\"} 1",
+        "chat_messages_created_total 1",
+        "chat_client_messages_total{kind=\"Hello\"} 1",
+        "chat_client_messages_total{kind=\"SendMessage\"} 1",
+        "chat_storage_query_duration_seconds_count{operation=\"create_message\"} 1",
+    ] {
+        assert!(
+            body.lines().any(|l| l == line),
+            "missing {:?} in\n{}",
+            line,
+            body
+        );
+    }
+}
+
+#[tokio::test]
+async fn health_checks_pass_with_reachable_storage() {
+    let server = TestServer::start().await;
+    assert_eq!(server.get("/healthz").await, (200, "ok".to_string()));
+    assert_eq!(server.get("/readyz").await, (200, "ok".to_string()));
+}
+
+#[tokio::test]
+async fn draining_asks_sessions_to_reconnect() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+
+    let state = server.state.clone();
+    let drained = tokio::spawn(async move { state.drain("deploying").await });
+    let reason = alice
+        .expect(|msg| match msg {
+            ServerMessage::Reconnect { reason } => Some(reason),
+            _ => None,
+        })
+        .await;
+    assert_eq!(reason, "deploying");
+    assert!(drained.await.unwrap(), "the session did not finish");
+    assert_eq!(server.get("/readyz").await.0, 503);
+}
+
+#[tokio::test]
+async fn requests_over_the_rate_limit_are_rejected() {
+    let mut config = Config::default();
+    config.rate_limits.session.send_message = RateLimit {
+        burst: 1,
+        per_minute: 1,
+    };
+    let server = TestServer::with_config(config).await;
+    let mut alice = server.connect("alice").await;
+    let guild = create_guild(&mut alice, "slow mode").await;
+    let send = |nonce| {
+        Request::SendMessage(SendMessage {
+            channel_id: text_channel_id(&guild),
+            content: "hello?".to_string(),
+            nonce,
+            reply_to: None,
+        })
+    };
+
+    alice.request_ok(send(1)).await;
+    let replies = alice.request(send(2)).await;
+    let [ServerMessage::RateLimited { retry_after_ms, .. }] = replies.as_slice() else {
+        panic!("expected RateLimited, got {:?}", replies);
+    };
+    assert!(*retry_after_ms > 50_000, "retry after {}ms", retry_after_ms);
+
+    // Other categories have buckets of their own.
+    alice
+        .request_ok(Request::FetchMessages(FetchMessages {
+            channel_id: text_channel_id(&guild),
+            cursor: MessageCursor::Latest,
+            limit: 10,
+        }))
+        .await;
+}
+
+#[tokio::test]
+async fn oversized_length_prefixes_are_rejected_before_allocating() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect("alice").await;
+
+    // A SendMessage whose content claims to be a terabyte long.
+    let mut frame = vec![1, 1, 1, 1, 253];
+    frame.extend_from_slice(&(1u64 << 40).to_le_bytes());
+    alice.send_bytes(frame).await;
+    let err = alice
+        .expect(|msg| match msg {
+            ServerMessage::Error(err) => Some(err),
+            _ => None,
+        })
+        .await;
+    assert_eq!(err.code, ErrorCode::InvalidRequest);
+}
@@ -0,0 +1,289 @@
+//! Boots the real router on an ephemeral port and talks to it the way the app does:
+//! `POST /register` for a token, then the binary protocol over `/ws`.
+//!
+//! Storage is in memory unless `TEST_DATABASE_URL` points at a Postgres server, e.g.
+//! `TEST_DATABASE_URL=postgres://postgres@localhost cargo test`. Every test server then gets
+//! a fresh, migrated database named `chat_test_<random>`, which is left behind afterwards.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use common::{
+    AuthResponse, ClientMessage, Credentials, Hello, PROTOCOL_VERSION, Ready, Request, RequestId,
+    Resume, ServerMessage, SessionId, User, Welcome,
+};
+use futures_util::{SinkExt, StreamExt};
+use rand::{Rng, distr::Alphanumeric};
+use server::{
+    AppState,
+    config::Config,
+    storage::{IdGenerator, MemoryStorage, PostgresStorage, Storage},
+};
+use sqlx::{Connection, Executor, PgConnection, postgres::PgConnectOptions};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async,
+    tungstenite::{Message, client::IntoClientRequest},
+};
+
+/// How long a test waits for a message before it fails.
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Storage for a new test server, see the module docs.
+async fn storage() -> Arc<dyn Storage> {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        return Arc::new(MemoryStorage::default());
+    };
+    let options: PgConnectOptions = url.parse().expect("invalid TEST_DATABASE_URL");
+    let suffix: String = rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(12)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+    let database = format!("chat_test_{}", suffix);
+    let mut admin = PgConnection::connect_with(&options).await.unwrap();
+    admin
+        .execute(format!("CREATE DATABASE {}", database).as_str())
+        .await
+        .unwrap();
+    admin.close().await.unwrap();
+
+    let storage =
+        PostgresStorage::connect_with(options.database(&database), 4, IdGenerator::default())
+            .await
+            .unwrap();
+    storage.run_migrations().await.unwrap();
+    Arc::new(storage)
+}
+
+pub struct TestServer {
+    addr: SocketAddr,
+    pub state: Arc<AppState>,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::with_config(Config::default()).await
+    }
+
+    /// Starts a server with `config`. Its database settings are ignored in favour of
+    /// [`storage`].
+    pub async fn with_config(config: Config) -> Self {
+        let state = Arc::new(AppState::new(storage().await, &config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = server::router(state.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        Self { addr, state }
+    }
+
+    /// Registers `username` and returns the server's answer.
+    pub async fn register(&self, username: &str) -> AuthResponse {
+        let credentials = Credentials {
+            username: username.to_string(),
+            password: "correct horse battery staple".to_string(),
+        };
+        let body = self.post("/register", &credentials.encode().unwrap()).await;
+        AuthResponse::decode(&body).unwrap()
+    }
+
+    /// Registers `username` and opens a fresh session for them.
+    pub async fn connect(&self, username: &str) -> TestClient {
+        let AuthResponse::Ok { token, .. } = self.register(username).await else {
+            panic!("failed to register {}", username);
+        };
+        let mut client = self.open(&token).await;
+        client.hello().await;
+        client
+    }
+
+    /// Opens a websocket authenticated with `token` without sending anything on it.
+    pub async fn open(&self, token: &str) -> TestClient {
+        let mut request = format!("ws://{}/ws", self.addr)
+            .into_client_request()
+            .unwrap();
+        request.headers_mut().insert(
+            "Authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let (ws, _) = connect_async(request).await.unwrap();
+        TestClient {
+            ws,
+            token: token.to_string(),
+            session_id: None,
+            last_seq: 0,
+            next_request_id: 0,
+            ready: None,
+        }
+    }
+
+    /// A bare-bones HTTP/1.1 POST, enough for the bincode endpoints.
+    async fn post(&self, path: &str, body: &[u8]) -> Vec<u8> {
+        self.http("POST", path, body).await.1
+    }
+
+    /// A bare-bones HTTP/1.1 GET returning the status code and the body as text.
+    pub async fn get(&self, path: &str) -> (u16, String) {
+        let (status, body) = self.http("GET", path, &[]).await;
+        (status, String::from_utf8(body).unwrap())
+    }
+
+    async fn http(&self, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(self.addr).await.unwrap();
+        let head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            method,
+            path,
+            self.addr,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(body).await.unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        let status = std::str::from_utf8(&response[9..12])
+            .ok()
+            .and_then(|status| status.parse().ok())
+            .expect("malformed HTTP status line");
+        let body_start = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("malformed HTTP response")
+            + 4;
+        (status, response.split_off(body_start))
+    }
+}
+
+pub struct TestClient {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    pub token: String,
+    pub session_id: Option<SessionId>,
+    pub last_seq: u64,
+    next_request_id: RequestId,
+    pub ready: Option<Ready>,
+}
+
+impl TestClient {
+    pub fn user(&self) -> &User {
+        &self.ready.as_ref().expect("no Ready received yet").user
+    }
+
+    /// Performs the handshake and waits for the [`Ready`] snapshot.
+    pub async fn hello(&mut self) {
+        self.send(ClientMessage::Hello(Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client_name: "integration test".to_string(),
+            capabilities: Vec::new(),
+        }))
+        .await;
+        let ServerMessage::Welcome(Welcome { session_id, .. }) = self.recv().await else {
+            panic!("expected Welcome");
+        };
+        self.session_id = Some(session_id);
+        let ready = self
+            .expect(|msg| match msg {
+                ServerMessage::Ready(ready) => Some(ready),
+                _ => None,
+            })
+            .await;
+        self.ready = Some(ready);
+    }
+
+    /// Resumes `session_id` instead of saying hello, as a reconnecting app would.
+    pub async fn resume(&mut self, session_id: SessionId, last_seq: u64) {
+        self.session_id = Some(session_id);
+        self.last_seq = last_seq;
+        self.send(ClientMessage::Resume(Resume {
+            session_id,
+            last_seq,
+        }))
+        .await;
+    }
+
+    pub async fn send(&mut self, msg: ClientMessage) {
+        self.send_bytes(msg.encode().unwrap()).await;
+    }
+
+    /// Sends a binary frame as is, for messages a well-behaved client would never encode.
+    pub async fn send_bytes(&mut self, bytes: Vec<u8>) {
+        self.ws.send(Message::binary(bytes)).await.unwrap();
+    }
+
+    /// Returns the next message, unwrapped from [`ServerMessage::Sequenced`] and skipping
+    /// heartbeats.
+    pub async fn recv(&mut self) -> ServerMessage {
+        loop {
+            let frame = tokio::time::timeout(RECV_TIMEOUT, self.ws.next())
+                .await
+                .expect("timed out waiting for a server message")
+                .expect("the server closed the socket")
+                .unwrap();
+            let Message::Binary(data) = frame else {
+                continue;
+            };
+            match ServerMessage::decode(&data).unwrap() {
+                ServerMessage::Sequenced { seq, message } => {
+                    assert_eq!(seq, self.last_seq + 1, "sequence numbers must not skip");
+                    self.last_seq = seq;
+                    return *message;
+                }
+                ServerMessage::Heartbeat => {}
+                msg => return msg,
+            }
+        }
+    }
+
+    /// Skips messages until `f` picks one.
+    pub async fn expect<T>(&mut self, mut f: impl FnMut(ServerMessage) -> Option<T>) -> T {
+        loop {
+            if let Some(picked) = f(self.recv().await) {
+                return picked;
+            }
+        }
+    }
+
+    /// Sends `request` and returns everything the server sent up to and including its
+    /// `Ack`, `Error` or `RateLimited`.
+    pub async fn request(&mut self, request: Request) -> Vec<ServerMessage> {
+        self.next_request_id += 1;
+        let request_id = self.next_request_id;
+        self.send(ClientMessage::Request {
+            request_id,
+            request,
+        })
+        .await;
+
+        let mut replies = Vec::new();
+        loop {
+            let msg = self.recv().await;
+            let done = match &msg {
+                ServerMessage::Ack(ack) => ack.request_id == request_id,
+                ServerMessage::Error(err) => err.request_id == Some(request_id),
+                ServerMessage::RateLimited { request_id: id, .. } => *id == request_id,
+                _ => false,
+            };
+            replies.push(msg);
+            if done {
+                return replies;
+            }
+        }
+    }
+
+    /// Like [`TestClient::request`] but fails the test unless the request was acked.
+    pub async fn request_ok(&mut self, request: Request) -> Vec<ServerMessage> {
+        let replies = self.request(request).await;
+        if let Some(ServerMessage::Error(err)) = replies.last() {
+            panic!("request failed: {:?}", err);
+        }
+        replies
+    }
+
+    pub async fn close(mut self) {
+        let _ = self.ws.close(None).await;
+    }
+}